@@ -0,0 +1,402 @@
+use clap::{Parser, Subcommand};
+use serde_json::json;
+use std::{
+    fmt,
+    io::{stdout, Write},
+    sync::mpsc::{self, Receiver},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    events::{Event, IrcEvents, Login, Transport},
+    logger::format_line,
+    types::{Init, Network, NetworkChannel},
+};
+
+/// How long to wait for the server to log us in
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the server to echo a sent message
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Parser)]
+#[command(about = "Terminal client for The Lounge")]
+pub struct Args {
+    /// Address of the Lounge instance
+    #[arg(long, env = "LOUNGE_URL")]
+    url: Option<String>,
+    /// Lounge username
+    #[arg(long, env = "LOUNGE_USER")]
+    user: Option<String>,
+    /// Lounge password
+    #[arg(long, env = "LOUNGE_PASSWORD", hide_env_values = true)]
+    password: Option<String>,
+    /// Run a single command without starting the TUI
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+impl Args {
+    pub fn login(&self) -> Login {
+        let default = Login::default();
+        Login {
+            url: self.url.clone().unwrap_or(default.url),
+            user: self.user.clone().unwrap_or(default.user),
+            password: self.password.clone().unwrap_or(default.password),
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Send a message to a channel and exit
+    Send {
+        /// Network the channel belongs to
+        #[arg(long)]
+        network: Option<String>,
+        /// Channel or query to send the message to
+        #[arg(long)]
+        channel: String,
+        text: String,
+    },
+    /// Print messages of a channel as they arrive
+    Tail {
+        channel: String,
+        /// Network the channel belongs to
+        #[arg(long)]
+        network: Option<String>,
+        /// Print messages as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
+    /// List networks and their channels
+    List {
+        /// Print networks as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    /// Server rejected the username or password
+    Auth,
+    /// Server couldn't be reached or didn't respond
+    Connection(String),
+    /// No channel or network matched the given name
+    UnknownChannel(String),
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> i32 {
+        // 1 is left for panics and 2 is used by clap for usage errors
+        match self {
+            CliError::Auth => 3,
+            CliError::Connection(_) => 4,
+            CliError::UnknownChannel(_) => 5,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Auth => write!(f, "authentication failed"),
+            CliError::Connection(err) => write!(f, "connection failed: {err}"),
+            CliError::UnknownChannel(name) => write!(f, "unknown channel: {name}"),
+        }
+    }
+}
+
+/// Run the command and return the exit code of the process
+pub fn run(login: Login, command: Command) -> i32 {
+    let mut events = match IrcEvents::connect(login) {
+        Ok(events) => events,
+        Err(err) => return report(CliError::Connection(err.to_string())),
+    };
+
+    let woken = wake_on_events(&mut events);
+    let result = wait_for_init(&mut events, &woken).and_then(|init| match command {
+        Command::Send {
+            network,
+            channel,
+            text,
+        } => send(
+            &mut events,
+            &woken,
+            &init,
+            network.as_deref(),
+            &channel,
+            &text,
+        ),
+        Command::Tail {
+            channel,
+            network,
+            json,
+        } => tail(
+            &mut events,
+            &woken,
+            &init,
+            network.as_deref(),
+            &channel,
+            json,
+        ),
+        Command::List { json } => {
+            list(&init, json);
+            Ok(())
+        }
+    });
+
+    // The command is done either way, a socket that's already gone is fine
+    let _ = events.disconnect();
+    match result {
+        Ok(()) => 0,
+        Err(err) => report(err),
+    }
+}
+
+fn report(err: CliError) -> i32 {
+    eprintln!("error: {err}");
+    err.exit_code()
+}
+
+/// Get a message on the receiver whenever the socket thread queues events
+fn wake_on_events(events: &mut dyn Transport) -> Receiver<()> {
+    let (sender, receiver) = mpsc::channel();
+    events.set_waker(Box::new(move || {
+        let _ = sender.send(());
+    }));
+    receiver
+}
+
+/// Handle events until `handle` returns a result, or fail once `timeout`
+/// has passed without one
+fn wait_for<T>(
+    events: &mut dyn Transport,
+    woken: &Receiver<()>,
+    timeout: Duration,
+    doing: &str,
+    mut handle: impl FnMut(Event) -> Option<Result<T, CliError>>,
+) -> Result<T, CliError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match events.event() {
+            Some(event) => {
+                if let Some(result) = handle(event) {
+                    return result;
+                }
+            }
+            None => {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if woken.recv_timeout(left).is_err() {
+                    return Err(CliError::Connection(format!("timed out while {doing}")));
+                }
+            }
+        }
+    }
+}
+
+/// Wait until the server has logged us in and sent the initial state
+fn wait_for_init(events: &mut dyn Transport, woken: &Receiver<()>) -> Result<Init, CliError> {
+    wait_for(
+        events,
+        woken,
+        LOGIN_TIMEOUT,
+        "logging in",
+        |event| match event {
+            Event::Init(init) => Some(Ok(init)),
+            Event::AuthFailed => Some(Err(CliError::Auth)),
+            _ => None,
+        },
+    )
+}
+
+/// Find a channel by name, optionally limited to a single network
+fn find_channel<'a>(
+    init: &'a Init,
+    network: Option<&str>,
+    name: &str,
+) -> Result<(&'a Network, &'a NetworkChannel), CliError> {
+    let networks: Vec<&Network> = match network {
+        Some(network) => init
+            .networks
+            .iter()
+            .filter(|n| n.name.eq_ignore_ascii_case(network))
+            .collect(),
+        None => init.networks.iter().collect(),
+    };
+
+    if networks.is_empty() {
+        return Err(CliError::UnknownChannel(format!(
+            "no network named {}",
+            network.unwrap_or_default()
+        )));
+    }
+
+    let mut found = networks.into_iter().flat_map(|network| {
+        network
+            .channels
+            .iter()
+            .filter(|c| c.name.eq_ignore_ascii_case(name))
+            .map(move |c| (network, c))
+    });
+
+    match (found.next(), found.next()) {
+        (Some(channel), None) => Ok(channel),
+        (Some(_), Some(_)) => Err(CliError::UnknownChannel(format!(
+            "{name} exists in multiple networks, use --network"
+        ))),
+        (None, _) => Err(CliError::UnknownChannel(name.into())),
+    }
+}
+
+/// Send the text and wait until the server echoes it back, which tells that
+/// it got through. Commands other than `/me` don't echo anything, they're
+/// done once sent.
+fn send(
+    events: &mut dyn Transport,
+    woken: &Receiver<()>,
+    init: &Init,
+    network: Option<&str>,
+    channel: &str,
+    text: &str,
+) -> Result<(), CliError> {
+    let (network, channel) = find_channel(init, network, channel)?;
+    events
+        .emit_input(text, channel.id)
+        .map_err(|err| CliError::Connection(err.to_string()))?;
+    if text.starts_with('/') && !text.starts_with("/me ") {
+        return Ok(());
+    }
+
+    wait_for(
+        events,
+        woken,
+        SEND_TIMEOUT,
+        "sending",
+        |event| match event {
+            Event::Msg(msg)
+                if msg.chan == channel.id
+                    && msg.msg.from.nick.as_deref() == Some(network.nick.as_str()) =>
+            {
+                Some(Ok(()))
+            }
+            Event::Disconnected => Some(Err(CliError::Connection("connection closed".into()))),
+            Event::AuthFailed | Event::SignedOut => Some(Err(CliError::Auth)),
+            _ => None,
+        },
+    )
+}
+
+fn tail(
+    events: &mut dyn Transport,
+    woken: &Receiver<()>,
+    init: &Init,
+    network: Option<&str>,
+    channel: &str,
+    json: bool,
+) -> Result<(), CliError> {
+    let (network, channel) = find_channel(init, network, channel)?;
+
+    loop {
+        let msg = match events.event() {
+            Some(Event::Msg(msg)) if msg.chan == channel.id => msg,
+            Some(Event::Disconnected) => {
+                return Err(CliError::Connection("connection closed".into()))
+            }
+            Some(Event::AuthFailed | Event::SignedOut) => return Err(CliError::Auth),
+            Some(_) => continue,
+            None => {
+                // Every queued event sends a wake up, so this can't miss any
+                if woken.recv().is_err() {
+                    return Err(CliError::Connection("connection closed".into()));
+                }
+                continue;
+            }
+        };
+
+        let line = if json {
+            json!({"network": network.name, "channel": channel.name, "msg": msg.msg}).to_string()
+        } else {
+            format_line(&msg.msg)
+        };
+
+        let mut out = stdout().lock();
+        // Reader went away, nothing left to do
+        if writeln!(out, "{line}").and_then(|_| out.flush()).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn list(init: &Init, json: bool) {
+    for network in &init.networks {
+        if json {
+            let channels: Vec<_> = network
+                .channels
+                .iter()
+                .map(|c| json!({"id": c.id, "name": c.name, "type": c.type_}))
+                .collect();
+            println!(
+                "{}",
+                json!({"network": network.name, "nick": network.nick, "channels": channels})
+            );
+        } else {
+            println!("{}", network.name);
+            for channel in &network.channels {
+                println!("  {}", channel.name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{send, tail, wake_on_events, CliError};
+    use crate::events::{Event, FakeTransport};
+
+    const INIT: &str = include_str!("tui/fixtures/init.json");
+
+    #[test]
+    fn tail_stops_when_the_connection_does() {
+        let init = serde_json::from_str(INIT).unwrap();
+        let mut transport = FakeTransport::default();
+        let woken = wake_on_events(&mut transport);
+
+        transport.push(Event::Disconnected);
+        let result = tail(&mut transport, &woken, &init, None, "#rust", false);
+        assert!(matches!(result, Err(CliError::Connection(_))));
+
+        transport.push(Event::SignedOut);
+        let result = tail(&mut transport, &woken, &init, None, "#rust", false);
+        assert!(matches!(result, Err(CliError::Auth)));
+    }
+
+    #[test]
+    fn send_waits_for_the_echo() {
+        let init = serde_json::from_str(INIT).unwrap();
+        let mut transport = FakeTransport::default();
+        let woken = wake_on_events(&mut transport);
+        let msg = |nick: &str| {
+            let msg = json!({"chan": 2, "msg": {
+                "from": {"mode": "", "nick": nick}, "id": 30, "self": nick == "duck",
+                "text": "hi", "time": "2024-04-20T11:00:00.000Z", "type": "message",
+            }});
+            Event::Msg(serde_json::from_value(msg).unwrap())
+        };
+
+        // Messages of others on the channel aren't the echo
+        transport.push(msg("ferris"));
+        transport.push(Event::Disconnected);
+        let result = send(&mut transport, &woken, &init, None, "#rust", "hi");
+        assert!(matches!(result, Err(CliError::Connection(_))));
+        assert_eq!(transport.take_emitted()[0].1["text"], "hi");
+
+        transport.push(msg("duck"));
+        let result = send(&mut transport, &woken, &init, None, "#rust", "hi");
+        assert!(result.is_ok());
+    }
+}
@@ -0,0 +1,339 @@
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use toml_edit::{DocumentMut, Item, Table};
+
+use crate::pattern::{Glob, Regex};
+
+/// Local settings of the client, read from `config.toml` in the user's
+/// config directory
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub log: LogConfig,
+    pub buffer: BufferConfig,
+    /// Commands that expand into other input, by name without the slash
+    pub aliases: BTreeMap<String, Alias>,
+    pub status: StatusConfig,
+    /// Rules for hiding or dimming messages
+    pub filters: Vec<Filter>,
+    /// Zone that message times and the clock are shown in
+    pub timezone: TimeZone,
+    /// Channels that show status messages differently than the synced
+    /// setting, by network and channel name
+    pub status_messages: BTreeMap<String, BTreeMap<String, StatusMessages>>,
+}
+
+impl Config {
+    /// Path of the config file, if the platform has a config directory
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("thelounge-tui").join("config.toml"))
+    }
+
+    /// Load the config file, falling back to defaults if it doesn't exist
+    pub fn load() -> Result<Self, String> {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str::<Self>(&content)
+                .map_err(|err| err.to_string())
+                .and_then(|config| config.validate().map(|_| config))
+                .map_err(|err| format!("{}: {err}", path.display())),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(format!("{}: {err}", path.display())),
+        }
+    }
+
+    /// Check what the types alone don't, like the delays in aliases
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, alias) in &self.aliases {
+            for line in alias.lines() {
+                if let Some(Err(err)) = Alias::delay(&line) {
+                    return Err(format!("alias {name}: {err}"));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Set or with None remove the status messages override of a channel in
+    /// the config file. The rest of the file, comments included, is kept.
+    pub fn save_status_messages(
+        path: &Path,
+        network: &str,
+        channel: &str,
+        mode: Option<StatusMessages>,
+    ) -> Result<(), String> {
+        let error = |err: &dyn fmt::Display| format!("{}: {err}", path.display());
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(error(&err)),
+        };
+        let mut doc: DocumentMut = content.parse().map_err(|err| error(&err))?;
+
+        match mode {
+            Some(mode) => {
+                let mut networks = Table::new();
+                // Only the per network tables need a header
+                networks.set_implicit(true);
+                let channels = doc
+                    .entry("status_messages")
+                    .or_insert(Item::Table(networks))
+                    .as_table_like_mut()
+                    .and_then(|networks| {
+                        networks
+                            .entry(network)
+                            .or_insert(toml_edit::table())
+                            .as_table_like_mut()
+                    })
+                    .ok_or_else(|| error(&"status_messages isn't a table of tables"))?;
+                channels.insert(channel, toml_edit::value(mode.name()));
+            }
+            None => {
+                let networks = doc.get_mut("status_messages");
+                if let Some(channels) = networks
+                    .and_then(|networks| networks.get_mut(network))
+                    .and_then(|channels| channels.as_table_like_mut())
+                {
+                    channels.remove(channel);
+                }
+            }
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| error(&err))?;
+        }
+        fs::write(path, doc.to_string()).map_err(|err| error(&err))
+    }
+}
+
+/// `local` for the zone of the system, `UTC` or a fixed offset like `+02:00`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum TimeZone {
+    #[default]
+    Local,
+    Offset(FixedOffset),
+}
+
+impl TryFrom<String> for TimeZone {
+    type Error = String;
+
+    fn try_from(zone: String) -> Result<Self, Self::Error> {
+        match zone.as_str() {
+            "local" => Ok(TimeZone::Local),
+            "UTC" | "utc" => Ok(TimeZone::utc()),
+            offset => offset
+                .parse()
+                .map(TimeZone::Offset)
+                .map_err(|_| format!("invalid time zone {zone}, expected local, UTC or +HH:MM")),
+        }
+    }
+}
+
+impl TimeZone {
+    pub fn utc() -> Self {
+        TimeZone::Offset(FixedOffset::east_opt(0).expect("Zero offset is valid"))
+    }
+
+    /// Format an ISO 8601 time of the Lounge, None if it doesn't parse
+    pub fn format(&self, time: &str, format: &str) -> Option<String> {
+        let time = DateTime::parse_from_rfc3339(time).ok()?;
+        Some(self.format_utc(time.with_timezone(&Utc), format))
+    }
+
+    pub fn format_utc(&self, time: DateTime<Utc>, format: &str) -> String {
+        match self {
+            TimeZone::Local => time.with_timezone(&chrono::Local).format(format),
+            TimeZone::Offset(offset) => time.with_timezone(offset).format(format),
+        }
+        .to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Irssi like plain text lines
+    Text,
+    /// One JSON encoded message per line
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotate {
+    /// Single file per channel
+    Never,
+    /// New file every day
+    Daily,
+    /// New file every month
+    Monthly,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub enabled: bool,
+    /// Directory where the logs are written, `<dir>/<network>/<channel>.log`
+    pub dir: Option<PathBuf>,
+    pub format: LogFormat,
+    pub rotate: LogRotate,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: None,
+            format: LogFormat::Text,
+            rotate: LogRotate::Never,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Configured log directory or the default one in the user's data directory
+    pub fn dir(&self) -> Option<PathBuf> {
+        self.dir
+            .clone()
+            .or_else(|| dirs::data_dir().map(|dir| dir.join("thelounge-tui").join("logs")))
+    }
+}
+
+/// Limits for the messages kept in memory. Only inactive channels are trimmed,
+/// the dropped messages are fetched again when scrolling back to them.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct BufferConfig {
+    /// Most messages kept per channel
+    pub channel_messages: usize,
+    /// Most messages kept over all channels
+    pub total_messages: usize,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            channel_messages: 1000,
+            total_messages: 20_000,
+        }
+    }
+}
+
+/// Items of the status bar below the chat
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StatusConfig {
+    /// Text with `{item}` placeholders for `clock`, `connection`, `network`,
+    /// `nick`, `channel`, `modes`, `users`, `activity` and `upload`. Words
+    /// whose items are all empty are left out. There's no lag item, the
+    /// socket library answers the server's pings without exposing them.
+    pub format: String,
+}
+
+impl Default for StatusConfig {
+    fn default() -> Self {
+        Self {
+            format:
+                "[{clock}] [{connection}] {nick}@{network} {channel} {modes} {users} {activity} {upload}"
+                    .into(),
+        }
+    }
+}
+
+/// How runs of joins, parts, quits and other status messages are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusMessages {
+    Shown,
+    /// Runs of them are summarized in one line that can be expanded
+    Condensed,
+    Hidden,
+}
+
+impl StatusMessages {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "shown" => Some(Self::Shown),
+            "condensed" => Some(Self::Condensed),
+            "hidden" => Some(Self::Hidden),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Shown => "shown",
+            Self::Condensed => "condensed",
+            Self::Hidden => "hidden",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterAction {
+    #[default]
+    Hide,
+    Dim,
+}
+
+/// Messages matching every given condition of a filter are hidden or
+/// dimmed. Filters without channels apply to all of them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Filter {
+    /// Glob on the nick of the sender, e.g. `*bot`
+    pub nick: Option<Glob>,
+    /// Glob on `nick!ident@host` of the sender
+    pub hostmask: Option<Glob>,
+    /// Message types, e.g. `join`, `part` and `quit`
+    pub types: Vec<String>,
+    /// Names of the channels the filter applies to
+    pub channels: Vec<String>,
+    /// Regex on the text of the message
+    pub text: Option<Regex>,
+    pub action: FilterAction,
+}
+
+/// Lines an alias expands into. `$1`, `$2-`, `$nick`, `$chan` and `$network`
+/// are substituted and `/delay <ms>` waits before the next line.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Alias {
+    Line(String),
+    Lines(Vec<String>),
+}
+
+impl Alias {
+    pub fn lines(&self) -> Vec<String> {
+        match self {
+            Alias::Line(line) => vec![line.clone()],
+            Alias::Lines(lines) => lines.clone(),
+        }
+    }
+
+    /// Wait of a `/delay <ms>` line, None if the line isn't a delay
+    pub fn delay(line: &str) -> Option<Result<Duration, String>> {
+        let ms = line.strip_prefix("/delay")?;
+        if !ms.is_empty() && !ms.starts_with(' ') {
+            return None;
+        }
+        let ms = ms.trim();
+        Some(
+            ms.parse()
+                .map(Duration::from_millis)
+                .map_err(|_| format!("invalid delay \"{ms}\", expected milliseconds")),
+        )
+    }
+}
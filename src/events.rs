@@ -1,9 +1,15 @@
 use rust_socketio::{client::Client, ClientBuilder, Payload};
-use serde_json::json;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
 use std::{
     collections::VecDeque,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
 };
+#[cfg(feature = "record")]
+use std::{fs::OpenOptions, io::Write};
 
 use crate::types;
 
@@ -13,143 +19,355 @@ pub enum Event {
     More(types::More),
     Names(types::Names),
     Join(types::Join),
+    Part(types::Part),
+    Quit(types::Quit),
+    SyncSort(types::SyncSort),
+    MuteChanged(types::MuteChanged),
+    Topic(types::Topic),
+    /// Commands the server handles, with the leading slash
+    Commands(Vec<String>),
+    /// Settings of the Lounge instance
+    Configuration(types::Configuration),
+    /// Token for a single upload
+    UploadAuth(String),
+    /// All synced client settings
+    SettingAll(serde_json::Map<String, Value>),
+    /// Synced client setting changed by another client
+    SettingNew(types::SettingNew),
+    /// Logged in clients of the user
+    Sessions(Vec<types::Session>),
+    /// Session of this client was revoked
+    SignedOut,
+    /// Recent highlights over all networks
+    Mentions(Vec<types::Mention>),
+    /// Socket to the Lounge is connected
+    Connected,
+    /// Socket to the Lounge was closed
+    Disconnected,
+    /// Server rejected the login credentials
+    AuthFailed,
+}
+
+/// Address of the Lounge instance and the credentials used to log in
+#[derive(Debug, Clone)]
+pub struct Login {
+    pub url: String,
+    pub user: String,
+    pub password: String,
+    /// Token of an earlier session, tried before the password
+    pub token: Option<String>,
+}
+
+impl Default for Login {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:9000".into(),
+            user: "duck".into(),
+            password: "duck".into(),
+            token: None,
+        }
+    }
+}
+
+/// Connection to a Lounge instance. Lets the state be driven by something
+/// other than a real socket, e.g. in tests.
+pub trait Transport {
+    /// Get a new event if there's one in the queue
+    fn event(&mut self) -> Option<Event>;
+
+    /// Call `waker` whenever a new event is queued
+    fn set_waker(&mut self, waker: Waker);
+
+    /// Send an event with JSON data to the server. A failed emit also queues
+    /// `Event::Disconnected`, so the connection state follows it.
+    fn emit(&self, event: &str, data: Value) -> Result<(), SocketError>;
+
+    fn disconnect(&mut self) -> Result<(), SocketError>;
+
+    /// Send input to to target channel ID
+    fn emit_input(&self, input: &str, target: u32) -> Result<(), SocketError> {
+        if input.is_empty() {
+            return Ok(());
+        }
+
+        self.emit("input", json!({"text": input, "target": target}))
+    }
+}
+
+/// Error of the socket to the Lounge, e.g. when it's unreachable or closed
+pub type SocketError = Box<rust_socketio::Error>;
+
+/// Called from the socket thread when new events are available
+pub type Waker = Box<dyn Fn() + Send>;
+
+/// Events received from the server, waiting to be handled
+#[derive(Default)]
+pub struct EventQueue {
+    events: Mutex<VecDeque<Event>>,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl EventQueue {
+    fn pop(&self) -> Option<Event> {
+        self.events.lock().expect("Poisoned lock").pop_front()
+    }
+
+    fn set_waker(&self, waker: Waker) {
+        let mut slot = self.waker.lock().expect("Poisoned lock");
+        let waker = slot.insert(waker);
+        // Wake up right away in case something was queued before the waker
+        if !self.events.lock().expect("Poisoned lock").is_empty() {
+            waker();
+        }
+    }
 }
 
 pub struct IrcEvents {
-    events: Arc<Mutex<VecDeque<Event>>>,
+    events: Arc<EventQueue>,
     client: Client,
 }
 
 impl IrcEvents {
-    pub fn new() -> Self {
-        let events = Arc::new(Mutex::new(VecDeque::new()));
-        let client = create_connection(events.clone());
-        Self { events, client }
+    /// Connect to the Lounge instance, returning an error if it's unreachable
+    pub fn connect(login: Login) -> Result<Self, SocketError> {
+        let events = Arc::new(EventQueue::default());
+        let client = create_connection(events.clone(), login)?;
+        Ok(Self { events, client })
     }
+}
 
-    pub fn disconnect(&mut self) {
-        self.client.disconnect().unwrap();
+impl Transport for IrcEvents {
+    fn event(&mut self) -> Option<Event> {
+        self.events.pop()
     }
 
-    /// Get a new event if there's on in the queue
-    pub fn event(&mut self) -> Option<Event> {
-        self.events.lock().expect("Poisoned lock").pop_front()
+    fn set_waker(&mut self, waker: Waker) {
+        self.events.set_waker(waker);
     }
 
-    /// Send input to to target channel ID
-    pub fn emit_input(&self, input: &str, target: u32) {
-        if input.is_empty() {
-            return;
+    fn emit(&self, event: &str, data: Value) -> Result<(), SocketError> {
+        self.client.emit(custom_event(event), data).map_err(|err| {
+            add_event(self.events.clone(), Event::Disconnected);
+            Box::new(err)
+        })
+    }
+
+    fn disconnect(&mut self) -> Result<(), SocketError> {
+        self.client.disconnect().map_err(Box::new)
+    }
+}
+
+/// In memory transport for tests. Clones share the same queues, so a test can
+/// keep one to inject events and inspect what the state emitted.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct FakeTransport {
+    events: Arc<EventQueue>,
+    emitted: Arc<Mutex<Vec<(String, Value)>>>,
+    /// Emits fail once set, like on a dropped socket
+    closed: Arc<AtomicBool>,
+}
+
+#[cfg(test)]
+impl FakeTransport {
+    /// Queue an event as if it came from the server
+    pub fn push(&self, event: Event) {
+        add_event(self.events.clone(), event);
+    }
+
+    /// Take everything emitted since the last call
+    pub fn take_emitted(&self) -> Vec<(String, Value)> {
+        std::mem::take(&mut *self.emitted.lock().expect("Poisoned lock"))
+    }
+
+    /// Make every following emit fail
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+impl Transport for FakeTransport {
+    fn event(&mut self) -> Option<Event> {
+        self.events.pop()
+    }
+
+    fn set_waker(&mut self, waker: Waker) {
+        self.events.set_waker(waker);
+    }
+
+    fn emit(&self, event: &str, data: Value) -> Result<(), SocketError> {
+        if self.closed.load(Ordering::Relaxed) {
+            self.push(Event::Disconnected);
+            return Err(Box::new(rust_socketio::Error::StoppedEngineIoSocket));
         }
 
-        let input = json!({"text": input, "target": target});
-        // TODO: error handling
-        self.client.emit("input", input).unwrap();
+        let mut emitted = self.emitted.lock().expect("Poisoned lock");
+        emitted.push((event.into(), data));
+        Ok(())
     }
 
-    pub fn emit<E, D>(&self, event: E, data: D)
-    where
-        E: Into<rust_socketio::Event>,
-        D: Into<Payload>,
-    {
-        self.client.emit(event, data).expect("Unreachable network")
+    fn disconnect(&mut self) -> Result<(), SocketError> {
+        Ok(())
     }
 }
 
-fn add_event(events: Arc<Mutex<VecDeque<Event>>>, event: Event) {
-    events.lock().expect("Poisoned lock").push_back(event);
+/// `rust_socketio` maps names like "open" into its own reserved events, so
+/// build Lounge's events by hand
+fn custom_event(event: &str) -> rust_socketio::Event {
+    rust_socketio::Event::Custom(event.into())
 }
 
-fn create_connection(events: Arc<Mutex<VecDeque<Event>>>) -> Client {
+fn add_event(events: Arc<EventQueue>, event: Event) {
+    events
+        .events
+        .lock()
+        .expect("Poisoned lock")
+        .push_back(event);
+
+    if let Some(waker) = &*events.waker.lock().expect("Poisoned lock") {
+        waker();
+    }
+}
+
+fn create_connection(events: Arc<EventQueue>, login: Login) -> Result<Client, SocketError> {
+    // Whether the last login attempt used the token, so a rejected token can
+    // fall back to the password
+    let used_token = Arc::new(AtomicBool::new(false));
+    let password = json!({"user": login.user, "password": login.password});
+
+    let client = ClientBuilder::new(login.url.as_str()).namespace("/");
+    // .transport_type(rust_socketio::TransportType::Any)
+
     let client = {
+        let used_token = used_token.clone();
+        let password = password.clone();
         let events = events.clone();
-        ClientBuilder::new("http://127.0.0.1:9000")
-            .namespace("/")
-            // .transport_type(rust_socketio::TransportType::Any)
-            .on("init", move |data, client| {
-                if let Payload::Text(mut data) = data {
-                    assert!(data.len() == 1);
-
-                    // this is stupid, I hate it but I don't know how to get the data ownedship otherwise
-                    let init: types::Init = serde_json::from_value(data.swap_remove(0)).unwrap();
-                    let active_channel = init.active;
-                    let type_ = init.active_channel().unwrap().type_.clone();
-                    add_event(events.clone(), Event::Init(init));
-
-                    // TODO: handle the open even from server
-                    client
-                        .emit("open", active_channel.to_string())
-                        .expect("Server unreachable");
-
-                    if type_ == "channel" {
-                        client
-                            .emit("names", json!({"target": active_channel}))
-                            .expect("Server unreachable");
-                    }
-                }
-            })
-            .on("auth:start", |_, client| {
-                let auth = json!({"user":"duck","password":"duck"});
-                client
-                    .emit("auth:perform", auth)
-                    .expect("Server unreachable");
-            })
+        client.on("auth:start", move |_, client| {
+            let auth = match &login.token {
+                Some(token) => json!({"user": login.user, "token": token}),
+                None => password.clone(),
+            };
+            used_token.store(login.token.is_some(), Ordering::Relaxed);
+            if client.emit("auth:perform", auth).is_err() {
+                add_event(events.clone(), Event::Disconnected)
+            }
+        })
     };
 
     let client = {
         let events = events.clone();
-        client.on("join", move |data, _| {
-            if let Payload::Text(mut data) = data {
-                assert!(data.len() == 1);
-
-                // this is stupid, I hate it but I don't know how to get the data ownedship otherwise
-                let join: types::Join = serde_json::from_value(data.swap_remove(0)).unwrap();
-                add_event(events.clone(), Event::Join(join))
-            }
+        client.on(rust_socketio::Event::Connect, move |_, _| {
+            add_event(events.clone(), Event::Connected)
         })
     };
 
     let client = {
         let events = events.clone();
-        client.on("names", move |data, _| {
-            if let Payload::Text(mut data) = data {
-                assert!(data.len() == 1);
+        client.on(rust_socketio::Event::Close, move |_, _| {
+            add_event(events.clone(), Event::Disconnected)
+        })
+    };
 
-                // this is stupid, I hate it but I don't know how to get the data ownedship otherwise
-                let names: types::Names = serde_json::from_value(data.swap_remove(0)).unwrap();
-                add_event(events.clone(), Event::Names(names))
+    let client = {
+        let events = events.clone();
+        client.on("auth:failed", move |_, client| {
+            // Tokens expire when their session is signed out elsewhere
+            if used_token.swap(false, Ordering::Relaxed) {
+                if client.emit("auth:perform", password.clone()).is_err() {
+                    add_event(events.clone(), Event::Disconnected)
+                }
+            } else {
+                add_event(events.clone(), Event::AuthFailed)
             }
         })
     };
 
     let client = {
         let events = events.clone();
-        client.on("more", move |data, _| {
-            if let Payload::Text(mut data) = data {
-                assert!(data.len() == 1);
-
-                // this is stupid, I hate it but I don't know how to get the data ownedship otherwise
-                let more: types::More = serde_json::from_value(data.swap_remove(0)).unwrap();
-                add_event(events.clone(), Event::More(more))
-            }
+        client.on("sign-out", move |_, _| {
+            add_event(events.clone(), Event::SignedOut)
         })
     };
 
-    let events = events.clone();
+    let client = on_json(client, "init", &events, Event::Init);
+    let client = on_json(client, "msg", &events, Event::Msg);
+    let client = on_json(client, "more", &events, Event::More);
+    let client = on_json(client, "names", &events, Event::Names);
+    let client = on_json(client, "join", &events, Event::Join);
+    let client = on_json(client, "part", &events, Event::Part);
+    let client = on_json(client, "quit", &events, Event::Quit);
+    let client = on_json(client, "sync_sort", &events, Event::SyncSort);
+    let client = on_json(client, "mute:changed", &events, Event::MuteChanged);
+    let client = on_json(client, "topic", &events, Event::Topic);
+    let client = on_json(client, "commands", &events, Event::Commands);
+    let client = on_json(client, "configuration", &events, Event::Configuration);
+    let client = on_json(client, "upload:auth", &events, Event::UploadAuth);
+    let client = on_json(client, "setting:all", &events, Event::SettingAll);
+    let client = on_json(client, "setting:new", &events, Event::SettingNew);
+    let client = on_json(client, "sessions:list", &events, Event::Sessions);
+    let client = on_json(client, "mentions:list", &events, Event::Mentions);
+
     let client = client
-        .on("msg", move |data, _| {
-            if let Payload::Text(mut data) = data {
-                assert!(data.len() == 1);
-                // this is stupid, I hate it but I don't know how to get the data ownedship otherwise
-                let msg: types::Msg = serde_json::from_value(data.swap_remove(0)).unwrap();
-                add_event(events.clone(), Event::Msg(msg));
-            }
-        })
         // .on("error", |err, _| panic!("{:#?}", err))
         .on_any(|_event, _payload, _| {
             // println!("{event:#?}");
             // println!("{payload:#?}");
         });
 
-    client.connect().expect("Connection failed")
+    #[cfg(feature = "record")]
+    let client = record(client);
+
+    client.connect().map_err(Box::new)
+}
+
+/// Queue the JSON payload of a server event as an `Event`. Payloads that
+/// don't parse, e.g. from a newer Lounge, are skipped instead of panicking
+/// on the socket thread.
+fn on_json<T: DeserializeOwned + 'static>(
+    client: ClientBuilder,
+    name: &str,
+    events: &Arc<EventQueue>,
+    event: fn(T) -> Event,
+) -> ClientBuilder {
+    let events = events.clone();
+    client.on(name, move |payload, _| {
+        let data = match payload {
+            Payload::Text(data) => data.into_iter().next(),
+            _ => None,
+        };
+        if let Some(Ok(data)) = data.map(serde_json::from_value) {
+            add_event(events.clone(), event(data));
+        }
+    })
+}
+
+/// Append every event from the server to the file in `LOUNGE_RECORD`, as a
+/// session for the mock server. Session tokens are replaced, the rest of the
+/// file is as private as the chat it recorded.
+#[cfg(feature = "record")]
+fn record(client: ClientBuilder) -> ClientBuilder {
+    let file = std::env::var_os("LOUNGE_RECORD")
+        .and_then(|path| OpenOptions::new().create(true).append(true).open(path).ok());
+    let Some(file) = file.map(Mutex::new) else {
+        return client;
+    };
+
+    client.on_any(move |event, payload, _| {
+        if let Payload::Text(data) = payload {
+            let event = String::from(event);
+            let mut data = data.into_iter().next().unwrap_or_default();
+            match event.as_str() {
+                "init" => data["token"] = json!("recorded"),
+                "sessions:list" => data
+                    .as_array_mut()
+                    .into_iter()
+                    .flatten()
+                    .for_each(|session| session["token"] = json!("recorded")),
+                _ => {}
+            }
+            let step = json!({"send": event, "data": data});
+            let _ = writeln!(file.lock().expect("Poisoned lock"), "{step}");
+        }
+    })
 }
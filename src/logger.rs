@@ -0,0 +1,424 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::{LogConfig, LogFormat, LogRotate},
+    types::ChannelMessage,
+};
+
+/// File in the log directory that remembers which messages of every channel
+/// have been logged
+const LOGGED_FILE: &str = ".logged.json";
+
+/// Older file that only had the newest logged message of every channel
+const MARKS_FILE: &str = ".marks.json";
+
+/// How often the logged spans are written to disk at most. They're saved on
+/// drop as well, so only a crash loses the last ones.
+const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Spans kept per channel, the oldest are forgotten first
+const MAX_SPANS: usize = 100;
+
+/// Position of a message in a channel. Message ids restart from 1 when the
+/// Lounge server restarts, so the time is compared first.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Mark {
+    time: String,
+    id: u32,
+}
+
+impl From<&ChannelMessage> for Mark {
+    fn from(msg: &ChannelMessage) -> Self {
+        Self {
+            time: msg.time.clone(),
+            id: msg.id,
+        }
+    }
+}
+
+/// Messages of a channel that have all been logged, from first to last
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Span {
+    first: Mark,
+    last: Mark,
+}
+
+impl Span {
+    fn contains(&self, mark: &Mark) -> bool {
+        self.first <= *mark && *mark <= self.last
+    }
+
+    fn overlaps(&self, other: &Span) -> bool {
+        self.first <= other.last && other.first <= self.last
+    }
+}
+
+/// Appends channel messages into per network and per channel log files
+pub struct Logger {
+    dir: PathBuf,
+    format: LogFormat,
+    rotate: LogRotate,
+    /// Logged messages, keyed by `<network>/<channel>`. Spans are sorted and
+    /// don't overlap.
+    logged: HashMap<String, Vec<Span>>,
+    /// Whether `logged` changed since it was saved
+    dirty: bool,
+    saved: Instant,
+}
+
+impl Logger {
+    /// Create a logger if logging is enabled in the config
+    pub fn new(config: &LogConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+
+        let dir = config.dir()?;
+        let logged = read_json(&dir.join(LOGGED_FILE)).unwrap_or_else(|| {
+            // Everything up to the old mark was logged
+            let marks: HashMap<String, Mark> = read_json(&dir.join(MARKS_FILE)).unwrap_or_default();
+            marks
+                .into_iter()
+                .map(|(key, last)| {
+                    let first = Mark {
+                        time: String::new(),
+                        id: 0,
+                    };
+                    (key, vec![Span { first, last }])
+                })
+                .collect()
+        });
+
+        Some(Self {
+            dir,
+            format: config.format,
+            rotate: config.rotate,
+            logged,
+            dirty: false,
+            saved: Instant::now(),
+        })
+    }
+
+    /// Append a batch of history, like the messages in `init` or `more`, to
+    /// the log of the channel. The batch is all of the channel's messages
+    /// between its first and last one. Messages that were logged before are
+    /// skipped, so history loaded on every connect is only written once.
+    pub fn log(
+        &mut self,
+        network: &str,
+        channel: &str,
+        messages: &[ChannelMessage],
+    ) -> io::Result<()> {
+        let (first, last) = match (messages.first(), messages.last()) {
+            (Some(first), Some(last)) => (Mark::from(first), Mark::from(last)),
+            _ => return Ok(()),
+        };
+
+        let key = format!("{}/{}", sanitize(network), sanitize(channel));
+        self.write(&key, network, channel, messages)?;
+        self.insert(&key, Span { first, last });
+        self.save_soon()
+    }
+
+    /// Append a message that just arrived. It follows the newest logged
+    /// message of the channel, as the client was connected in between.
+    pub fn append(&mut self, network: &str, channel: &str, msg: &ChannelMessage) -> io::Result<()> {
+        let key = format!("{}/{}", sanitize(network), sanitize(channel));
+        self.write(&key, network, channel, std::slice::from_ref(msg))?;
+
+        let mark = Mark::from(msg);
+        match self.logged.get_mut(&key).and_then(|spans| spans.last_mut()) {
+            Some(newest) if newest.contains(&mark) => {}
+            Some(newest) if newest.last < mark => {
+                newest.last = mark;
+                self.dirty = true;
+            }
+            _ => self.insert(
+                &key,
+                Span {
+                    first: mark.clone(),
+                    last: mark,
+                },
+            ),
+        }
+        self.save_soon()
+    }
+
+    /// Write the messages that aren't logged yet
+    fn write(
+        &self,
+        key: &str,
+        network: &str,
+        channel: &str,
+        messages: &[ChannelMessage],
+    ) -> io::Result<()> {
+        let spans = self.logged.get(key).map(Vec::as_slice).unwrap_or_default();
+        let mut current: Option<(PathBuf, File)> = None;
+
+        for msg in messages {
+            let mark = Mark::from(msg);
+            if spans.iter().any(|span| span.contains(&mark)) {
+                continue;
+            }
+
+            let path = self.file_path(network, channel, &msg.time);
+            let file = match &mut current {
+                Some((current_path, file)) if *current_path == path => file,
+                _ => {
+                    if let Some(parent) = path.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+                    &mut current.insert((path, file)).1
+                }
+            };
+
+            let line = match self.format {
+                LogFormat::Text => format_line(msg),
+                LogFormat::Json => serde_json::to_string(msg)?,
+            };
+            writeln!(file, "{line}")?;
+        }
+
+        Ok(())
+    }
+
+    /// Add a logged span, merging it with the ones it overlaps
+    fn insert(&mut self, key: &str, mut span: Span) {
+        let spans = self.logged.entry(key.into()).or_default();
+        if spans
+            .iter()
+            .any(|other| other.contains(&span.first) && other.contains(&span.last))
+        {
+            return;
+        }
+
+        spans.retain(|other| {
+            if !other.overlaps(&span) {
+                return true;
+            }
+            span.first = span.first.clone().min(other.first.clone());
+            span.last = span.last.clone().max(other.last.clone());
+            false
+        });
+        let at = spans.partition_point(|other| other.first < span.first);
+        spans.insert(at, span);
+        if spans.len() > MAX_SPANS {
+            spans.remove(0);
+        }
+        self.dirty = true;
+    }
+
+    /// Newest log file of the channel, if anything has been logged
+    pub fn latest(&self, network: &str, channel: &str) -> Option<PathBuf> {
+        let dir = self.dir.join(sanitize(network));
+        let channel = sanitize(channel);
+        let extension = self.extension();
+
+        fs::read_dir(&dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path
+                    .file_name()
+                    .and_then(|n| n.to_str())
+                    .unwrap_or_default();
+                name == format!("{channel}.{extension}")
+                    || (name.starts_with(&format!("{channel}-"))
+                        && name.ends_with(&format!(".{extension}")))
+            })
+            // Periods are zero padded so the newest file sorts last
+            .max()
+    }
+
+    /// Read a log file as displayable lines
+    pub fn read(&self, path: &Path) -> io::Result<Vec<String>> {
+        let content = fs::read_to_string(path)?;
+        let lines = content.lines();
+
+        Ok(match self.format {
+            LogFormat::Text => lines.map(String::from).collect(),
+            LogFormat::Json => lines
+                .map(|line| match serde_json::from_str::<ChannelMessage>(line) {
+                    Ok(msg) => format_line(&msg),
+                    Err(_) => line.into(),
+                })
+                .collect(),
+        })
+    }
+
+    fn extension(&self) -> &'static str {
+        match self.format {
+            LogFormat::Text => "log",
+            LogFormat::Json => "jsonl",
+        }
+    }
+
+    fn file_path(&self, network: &str, channel: &str, time: &str) -> PathBuf {
+        // Message times are ISO 8601, e.g. 2024-04-20T10:00:00.000Z
+        let period = match self.rotate {
+            LogRotate::Never => None,
+            LogRotate::Daily => time.get(..10),
+            LogRotate::Monthly => time.get(..7),
+        };
+
+        let channel = sanitize(channel);
+        let name = match period {
+            Some(period) => format!("{channel}-{period}.{}", self.extension()),
+            None => format!("{channel}.{}", self.extension()),
+        };
+
+        self.dir.join(sanitize(network)).join(name)
+    }
+
+    /// Save the logged spans unless they were saved recently
+    fn save_soon(&mut self) -> io::Result<()> {
+        if self.dirty && self.saved.elapsed() >= SAVE_INTERVAL {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&mut self) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(
+            self.dir.join(LOGGED_FILE),
+            serde_json::to_string(&self.logged)?,
+        )?;
+        self.dirty = false;
+        self.saved = Instant::now();
+        Ok(())
+    }
+}
+
+impl Drop for Logger {
+    fn drop(&mut self) {
+        if self.dirty {
+            let _ = self.save();
+        }
+    }
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let content = fs::read_to_string(path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+/// Make a network or channel name safe to use as a file name
+fn sanitize(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '\0' => '_',
+            c => c,
+        })
+        .collect();
+
+    // Don't allow hidden files or `..`
+    if name.starts_with('.') {
+        format!("_{name}")
+    } else {
+        name
+    }
+}
+
+/// Format a message as an irssi like line of plain text
+pub fn format_line(msg: &ChannelMessage) -> String {
+    let time = match (msg.time.get(..10), msg.time.get(11..19)) {
+        (Some(date), Some(time)) => format!("{date} {time}"),
+        _ => msg.time.clone(),
+    };
+
+    let nick = msg.from.nick.as_deref().unwrap_or("*");
+    let mode = msg.from.mode.as_deref().unwrap_or_default();
+    let host = msg
+        .hostmask
+        .as_deref()
+        .map(|host| format!(" [{host}]"))
+        .unwrap_or_default();
+
+    match msg.type_.as_str() {
+        "message" => format!("{time} <{mode}{nick}> {}", msg.text),
+        "action" => format!("{time}  * {nick} {}", msg.text),
+        "notice" => format!("{time} -{nick}- {}", msg.text),
+        "join" => format!("{time} -!- {nick}{host} has joined"),
+        "part" => format!("{time} -!- {nick}{host} has left [{}]", msg.text),
+        "quit" => format!("{time} -!- {nick}{host} has quit [{}]", msg.text),
+        type_ if msg.text.is_empty() => format!("{time} -!- {nick} {type_}"),
+        type_ => format!("{time} -!- {nick} {type_}: {}", msg.text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::fs;
+
+    use super::Logger;
+    use crate::{
+        config::{LogConfig, LogFormat, LogRotate},
+        types::ChannelMessage,
+    };
+
+    fn message(id: u32, time: &str) -> ChannelMessage {
+        serde_json::from_value(json!({
+            "id": id,
+            "time": time,
+            "type": "message",
+            "self": false,
+            "from": {"nick": "ferris"},
+            "text": format!("message {id}"),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn older_history_is_logged_once() {
+        let dir = std::env::temp_dir().join(format!("thelounge-tui-logs-{}", std::process::id()));
+        let config = LogConfig {
+            enabled: true,
+            dir: Some(dir.clone()),
+            format: LogFormat::Text,
+            rotate: LogRotate::Never,
+        };
+        let mut logger = Logger::new(&config).unwrap();
+
+        let init = [
+            message(10, "2024-04-20T10:00:00Z"),
+            message(12, "2024-04-20T10:02:00Z"),
+        ];
+        logger.log("Libera", "#rust", &init).unwrap();
+        logger
+            .append("Libera", "#rust", &message(13, "2024-04-20T10:03:00Z"))
+            .unwrap();
+        // History older than the logged messages from `more`
+        let more = [
+            message(5, "2024-04-20T09:00:00Z"),
+            message(10, "2024-04-20T10:00:00Z"),
+        ];
+        logger.log("Libera", "#rust", &more).unwrap();
+        drop(logger);
+
+        // Reconnecting sends the recent history again
+        let mut logger = Logger::new(&config).unwrap();
+        logger.log("Libera", "#rust", &init).unwrap();
+        logger.log("Libera", "#rust", &more).unwrap();
+
+        let ids: Vec<String> = logger
+            .read(&dir.join("Libera").join("#rust.log"))
+            .unwrap()
+            .iter()
+            .map(|line| line.rsplit(' ').next().unwrap().to_string())
+            .collect();
+        assert_eq!(ids, ["10", "12", "13", "5"]);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -1,7 +1,32 @@
+use clap::Parser;
+
+mod cli;
+mod config;
 mod events;
+mod logger;
+#[cfg(test)]
+mod mock;
+mod pattern;
+mod token;
 mod tui;
 mod types;
+mod upload;
 
 fn main() {
-    tui::run_tui().unwrap();
+    let args = cli::Args::parse();
+    let login = args.login();
+
+    match args.command {
+        Some(command) => std::process::exit(cli::run(login, command)),
+        None => {
+            let config = config::Config::load().unwrap_or_else(|err| {
+                eprintln!("error: invalid config: {err}");
+                std::process::exit(1);
+            });
+            if let Err(err) = tui::run_tui(login, config) {
+                eprintln!("error: {err}");
+                std::process::exit(1);
+            }
+        }
+    }
 }
@@ -0,0 +1,298 @@
+//! Offline Lounge server for tests.
+//!
+//! Speaks just enough Engine.IO (long polling only) and Socket.IO for
+//! `rust_socketio` to connect, then replays a [`Session`] to the client and
+//! records everything the client emits.
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::events::Login;
+
+/// How long a poll request is held open when there's nothing to send
+const POLL_HOLD: Duration = Duration::from_millis(100);
+
+/// Session with a single channel that the client can log into
+pub const BASIC_SESSION: &str = include_str!("fixtures/basic.json");
+
+/// Single step of a scripted or recorded session
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Step {
+    /// Send an event to the client
+    Send { send: String, data: Value },
+    /// Wait until the client emits the event before continuing the script
+    Expect { expect: String },
+}
+
+/// Steps the server goes through once a client connects
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    steps: Vec<Step>,
+}
+
+impl Session {
+    /// Parse a session from a stream of JSON encoded steps. Both JSON lines
+    /// and pretty printed objects are accepted.
+    pub fn parse(input: &str) -> Self {
+        let steps = serde_json::Deserializer::from_str(input)
+            .into_iter::<Step>()
+            .collect::<Result<_, _>>()
+            .expect("Invalid session");
+        Self { steps }
+    }
+
+    pub fn send(mut self, event: &str, data: Value) -> Self {
+        self.steps.push(Step::Send {
+            send: event.into(),
+            data,
+        });
+        self
+    }
+
+    pub fn expect(mut self, event: &str) -> Self {
+        self.steps.push(Step::Expect {
+            expect: event.into(),
+        });
+        self
+    }
+}
+
+#[derive(Default)]
+struct ServerState {
+    /// Steps of the session that haven't been played yet
+    script: VecDeque<Step>,
+    /// Engine.IO packets waiting for the next poll of the client
+    outgoing: VecDeque<String>,
+    /// Events the client has emitted
+    received: Vec<(String, Value)>,
+    /// Bodies posted to the upload endpoint, by token
+    uploads: Vec<(String, Vec<u8>)>,
+}
+
+impl ServerState {
+    fn queue_event(&mut self, event: &str, data: Value) {
+        self.outgoing
+            .push_back(format!("42{}", json!([event, data])));
+    }
+
+    /// Play the script until it has to wait for the client
+    fn advance(&mut self) {
+        while let Some(Step::Send { .. }) = self.script.front() {
+            if let Some(Step::Send { send, data }) = self.script.pop_front() {
+                self.queue_event(&send, data);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    state: Mutex<ServerState>,
+    /// Notified when packets are queued or events are received
+    changed: Condvar,
+}
+
+/// Lounge server running on a random local port
+pub struct MockServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+impl MockServer {
+    pub fn start(session: Session) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+        let addr = listener.local_addr().unwrap();
+        let shared = Arc::new(Shared::default());
+        shared.state.lock().unwrap().script = session.steps.into();
+
+        {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let shared = shared.clone();
+                    thread::spawn(move || handle_connection(stream, &shared));
+                }
+            });
+        }
+
+        Self { addr, shared }
+    }
+
+    /// Login pointing to this server
+    pub fn login(&self) -> Login {
+        Login {
+            url: format!("http://{}", self.addr),
+            ..Login::default()
+        }
+    }
+
+    /// Send an event to the client outside of the session script
+    pub fn send(&self, event: &str, data: Value) {
+        self.shared.state.lock().unwrap().queue_event(event, data);
+        self.shared.changed.notify_all();
+    }
+
+    /// Every event the client has emitted so far
+    pub fn received(&self) -> Vec<(String, Value)> {
+        self.shared.state.lock().unwrap().received.clone()
+    }
+
+    /// Data of every emitted event with the given name
+    pub fn received_event(&self, event: &str) -> Vec<Value> {
+        self.received()
+            .into_iter()
+            .filter(|(name, _)| name == event)
+            .map(|(_, data)| data)
+            .collect()
+    }
+
+    /// Bodies of the uploads so far, by token
+    pub fn uploads(&self) -> Vec<(String, Vec<u8>)> {
+        self.shared.state.lock().unwrap().uploads.clone()
+    }
+
+    /// Wait until the client has emitted `count` events with the given name
+    pub fn wait_for(&self, event: &str, count: usize) -> Vec<Value> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            let found: Vec<Value> = state
+                .received
+                .iter()
+                .filter(|(name, _)| name == event)
+                .map(|(_, data)| data.clone())
+                .collect();
+            if found.len() >= count {
+                return found;
+            }
+
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                let received = state.received.clone();
+                drop(state);
+                panic!("Client never emitted {event}, got {received:?}");
+            }
+            state = self.shared.changed.wait_timeout(state, timeout).unwrap().0;
+        }
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, shared: &Shared) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    let mut request_line = String::new();
+    if reader.read_line(&mut request_line).is_err() {
+        return;
+    }
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).is_err() || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return;
+    }
+
+    let response = match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", path, _] if !path.contains("sid=") => handshake(),
+        ["GET", _, _] => poll(shared),
+        ["POST", path, _] if path.starts_with("/uploads/new/") => {
+            let token = path.trim_start_matches("/uploads/new/").to_string();
+            let url = format!("uploads/{token}/file");
+            shared.state.lock().unwrap().uploads.push((token, body));
+            json!({ "url": url }).to_string()
+        }
+        ["POST", _, _] => {
+            receive(shared, &String::from_utf8_lossy(&body));
+            "ok".into()
+        }
+        _ => String::new(),
+    };
+
+    let _ = write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=UTF-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+        response.len()
+    );
+}
+
+fn handshake() -> String {
+    // Long ping interval so the client never expects a heartbeat from us
+    let handshake = json!({
+        "sid": "mock",
+        "upgrades": [],
+        "pingInterval": 600_000,
+        "pingTimeout": 600_000,
+        "maxPayload": 1_000_000,
+    });
+    format!("0{handshake}")
+}
+
+/// Long poll for the packets queued for the client
+fn poll(shared: &Shared) -> String {
+    let state = shared.state.lock().unwrap();
+    let (mut state, _) = shared
+        .changed
+        .wait_timeout_while(state, POLL_HOLD, |state| state.outgoing.is_empty())
+        .unwrap();
+
+    let packets: Vec<String> = state.outgoing.drain(..).collect();
+    packets.join("\x1e")
+}
+
+/// Handle the packets the client posted
+fn receive(shared: &Shared, body: &str) {
+    let mut state = shared.state.lock().unwrap();
+    for packet in body.split('\x1e') {
+        match packet.get(..2) {
+            // Socket.IO connect to the default namespace
+            Some("40") => {
+                state.outgoing.push_back(r#"40{"sid":"mock"}"#.into());
+                state.advance();
+            }
+            // Socket.IO event
+            Some("42") => {
+                let (event, data) = match serde_json::from_str::<Value>(&packet[2..]) {
+                    Ok(Value::Array(mut contents)) if !contents.is_empty() => {
+                        let event = contents.remove(0);
+                        let data = contents.into_iter().next().unwrap_or(Value::Null);
+                        (event.as_str().unwrap_or_default().to_string(), data)
+                    }
+                    _ => continue,
+                };
+
+                if let Some(Step::Expect { expect }) = state.script.front() {
+                    if *expect == event {
+                        state.script.pop_front();
+                        state.advance();
+                    }
+                }
+                state.received.push((event, data));
+            }
+            // Pongs, closes and disconnects need no answer
+            _ => {}
+        }
+    }
+
+    drop(state);
+    shared.changed.notify_all();
+}
@@ -0,0 +1,113 @@
+//! Patterns used by the message filters: IRC style globs for nicks and
+//! hostmasks, and regular expressions for text.
+
+use serde::Deserialize;
+
+/// Glob where `*` matches any run of characters and `?` a single one,
+/// ignoring case like IRC masks do
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "String")]
+pub struct Glob {
+    pattern: Vec<char>,
+}
+
+impl From<String> for Glob {
+    fn from(pattern: String) -> Self {
+        Self {
+            pattern: pattern.to_lowercase().chars().collect(),
+        }
+    }
+}
+
+impl Glob {
+    /// Only the last `*` is ever backtracked to, so the time stays linear in
+    /// the pattern times the text
+    pub fn matches(&self, text: &str) -> bool {
+        let text: Vec<char> = text.to_lowercase().chars().collect();
+        let (mut p, mut t) = (0, 0);
+        // Position after the last star and the text it started matching at
+        let mut star = None;
+
+        while t < text.len() {
+            match self.pattern.get(p) {
+                Some('*') => {
+                    p += 1;
+                    star = Some((p, t));
+                }
+                Some(&c) if c == '?' || c == text[t] => {
+                    p += 1;
+                    t += 1;
+                }
+                _ => match star {
+                    // Let the star take one more character
+                    Some((star_p, star_t)) => {
+                        p = star_p;
+                        t = star_t + 1;
+                        star = Some((star_p, t));
+                    }
+                    None => return false,
+                },
+            }
+        }
+        self.pattern[p..].iter().all(|&c| c == '*')
+    }
+}
+
+/// Regular expression matched anywhere in the text. Invalid ones are errors
+/// in the config.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Regex(regex::Regex);
+
+impl TryFrom<String> for Regex {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        regex::Regex::new(&pattern).map(Self)
+    }
+}
+
+impl Regex {
+    pub fn is_match(&self, text: &str) -> bool {
+        self.0.is_match(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Glob, Regex};
+
+    fn glob(pattern: &str) -> Glob {
+        Glob::from(pattern.to_string())
+    }
+
+    #[test]
+    fn globs_ignore_case() {
+        assert!(glob("*bot").matches("GitBot"));
+        assert!(!glob("*bot").matches("bots"));
+        assert!(glob("*!*@*.example.org").matches("x!~y@a.example.org"));
+        assert!(glob("n?ck").matches("nick"));
+        assert!(glob("a*b*").matches("aXbYb"));
+        assert!(!glob("a*b?").matches("ab"));
+    }
+
+    #[test]
+    fn many_stars_stay_fast() {
+        let text = "a".repeat(10_000);
+        assert!(!glob("*a*a*a*a*a*a*a*a*b").matches(&text));
+    }
+
+    #[test]
+    fn regexes_match_anywhere() {
+        let regex = Regex::try_from(r"^\[CI\] #\d+".to_string()).unwrap();
+        assert!(regex.is_match("[CI] #1234 passed"));
+        assert!(!regex.is_match("see [CI] #1"));
+        assert!(Regex::try_from("(unclosed".to_string()).is_err());
+    }
+
+    #[test]
+    fn nested_quantifiers_stay_fast() {
+        let regex = Regex::try_from("(a+)+$".to_string()).unwrap();
+        assert!(!regex.is_match(&format!("{}b!", "a".repeat(10_000))));
+    }
+}
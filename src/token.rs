@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use crate::events::Login;
+
+/// Token of the Lounge session, as written to the token file
+#[derive(Debug, Serialize, Deserialize)]
+struct Stored {
+    url: String,
+    user: String,
+    token: String,
+}
+
+/// Session token kept between runs, so that starting the client resumes the
+/// same session instead of logging in again and creating a new one
+pub struct TokenFile {
+    path: PathBuf,
+    url: String,
+    user: String,
+}
+
+impl TokenFile {
+    /// Token file in the user's data directory, if the platform has one
+    pub fn for_login(login: &Login) -> Option<Self> {
+        let path = dirs::data_dir()?.join("thelounge-tui").join("token.json");
+        Some(Self::new(path, login))
+    }
+
+    pub fn new(path: PathBuf, login: &Login) -> Self {
+        Self {
+            path,
+            url: login.url.clone(),
+            user: login.user.clone(),
+        }
+    }
+
+    /// Stored token, if it belongs to the same instance and user
+    pub fn load(&self) -> Option<String> {
+        let stored: Stored = serde_json::from_str(&fs::read_to_string(&self.path).ok()?).ok()?;
+        (stored.url == self.url && stored.user == self.user).then_some(stored.token)
+    }
+
+    pub fn save(&self, token: &str) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let stored = Stored {
+            url: self.url.clone(),
+            user: self.user.clone(),
+            token: token.into(),
+        };
+
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        // The token logs in without a password, keep it private
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(&stored)?)
+    }
+
+    /// Forget the token, e.g. after its session was signed out
+    pub fn remove(&self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TokenFile;
+    use crate::events::Login;
+
+    #[test]
+    fn tokens_belong_to_a_login() {
+        let dir = std::env::temp_dir().join(format!("thelounge-tui-token-{}", std::process::id()));
+        let path = dir.join("token.json");
+        let login = Login::default();
+
+        let file = TokenFile::new(path.clone(), &login);
+        assert_eq!(file.load(), None);
+        file.save("abc").unwrap();
+        assert_eq!(file.load().as_deref(), Some("abc"));
+
+        let other = Login {
+            user: "goose".into(),
+            ..Login::default()
+        };
+        assert_eq!(TokenFile::new(path.clone(), &other).load(), None);
+
+        file.remove().unwrap();
+        assert!(!path.exists());
+        file.remove().unwrap();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::types::Network;
 use ratatui::{
-    style::{Modifier, Style, Stylize},
-    text::Span,
+    style::{Color, Modifier, Style, Stylize},
+    text::{Line, Span},
     widgets::{Block, Borders, List, ListItem},
 };
 
@@ -18,17 +18,37 @@ impl ChannelsWidget {
                         channel.name.clone(),
                         Style::default().add_modifier(Modifier::BOLD),
                     )
+                } else if channel.type_ == "query" {
+                    (format!("  » {}", channel.name), Style::default().italic())
                 } else {
                     (format!("  {}", channel.name), Style::default())
                 };
 
+                let style = if channel.muted {
+                    style.add_modifier(Modifier::DIM)
+                } else {
+                    style
+                };
+
                 let style = if channel.id == active {
                     style.reversed()
                 } else {
                     style
                 };
 
-                channels.push(ListItem::new(Span::raw(text)).style(style));
+                let mut line = vec![Span::raw(text)];
+                // Muted channels don't bother the user with counters
+                if !channel.muted && channel.unread > 0 {
+                    let counter = Span::raw(format!(" ({})", channel.unread));
+                    let counter = if channel.highlight > 0 {
+                        counter.fg(Color::Yellow).bold()
+                    } else {
+                        counter
+                    };
+                    line.push(counter);
+                }
+
+                channels.push(ListItem::new(Line::from(line)).style(style));
             }
 
             // empty line to separate channels from eachother
@@ -1,37 +1,719 @@
 use ratatui::{
-    text::Span,
+    layout::{Alignment, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Text},
     widgets::{Block, Borders, List, ListDirection, ListItem},
 };
+use std::{
+    collections::{HashMap, HashSet},
+    iter::{Peekable, Rev},
+    rc::Rc,
+    slice,
+};
+use unicode_width::UnicodeWidthChar;
+
+use super::{state::StatusMessages, whois};
+use crate::{config::TimeZone, types::ChannelMessage};
+
+/// Cached messages are dropped all at once after this many
+const MAX_CACHED: usize = 10_000;
+
+/// Continuation lines aren't indented if it leaves less room than this
+const MIN_TEXT_WIDTH: usize = 10;
+
+/// Types of the status messages with their summary for one and for many, in
+/// the order they're summarized
+const STATUS_TYPES: &[(&str, &str, &str)] = &[
+    ("join", "user has joined", "users have joined"),
+    ("part", "user has left", "users have left"),
+    ("quit", "user has quit", "users have quit"),
+    ("nick", "user has changed nick", "users have changed nicks"),
+    ("kick", "user was kicked", "users were kicked"),
+    ("mode", "mode was set", "modes were set"),
+    (
+        "chghost",
+        "user has changed host",
+        "users have changed hosts",
+    ),
+    ("away", "user is away", "users are away"),
+    ("back", "user is back", "users are back"),
+];
+
+/// Wrapped lines of messages, for the width they were wrapped to. Message ids
+/// are unique over all channels so they're used as the key, until the Lounge
+/// restarts and the cache has to be cleared.
+#[derive(Default)]
+pub struct WrapCache {
+    width: usize,
+    lines: HashMap<u32, Rc<[String]>>,
+    /// Zone of the time column
+    zone: TimeZone,
+}
+
+impl WrapCache {
+    pub fn new(zone: TimeZone) -> Self {
+        Self {
+            zone,
+            ..Self::default()
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    fn get(&mut self, msg: &ChannelMessage, width: usize) -> Rc<[String]> {
+        if self.width != width || self.lines.len() >= MAX_CACHED {
+            self.width = width;
+            self.lines.clear();
+        }
+
+        self.lines
+            .entry(msg.id)
+            .or_insert_with(|| wrap_message(msg, width, self.zone).into())
+            .clone()
+    }
+}
+
+/// Condensed runs of status messages, known by the id of their oldest
+/// message: which are shown in full and which one the keys apply to
+#[derive(Default)]
+pub struct Runs {
+    pub expanded: HashSet<u32>,
+    selected: Option<u32>,
+    /// Runs in the chat when it was drawn last, from the newest
+    visible: Vec<u32>,
+}
+
+impl Runs {
+    /// Select the next older run in view, wrapping around to the newest
+    pub fn select_next(&mut self) {
+        let selected = self
+            .selected
+            .and_then(|id| self.visible.iter().position(|&v| v == id));
+        self.selected = match selected {
+            Some(pos) => self.visible.get(pos + 1).or(self.visible.first()),
+            None => self.visible.first(),
+        }
+        .copied();
+    }
+
+    /// Expand or collapse the selected run, or the newest one in view if
+    /// none is selected
+    pub fn toggle(&mut self) {
+        let id = self
+            .selected
+            .filter(|id| self.visible.contains(id))
+            .or(self.visible.first().copied());
+        if let Some(id) = id {
+            self.selected = Some(id);
+            if !self.expanded.remove(&id) {
+                self.expanded.insert(id);
+            }
+        }
+    }
+}
+
+/// How a message is shown in the chat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visibility {
+    Shown,
+    Dimmed,
+    Hidden,
+}
+
+fn is_status(msg: &ChannelMessage) -> bool {
+    STATUS_TYPES.iter().any(|(type_, ..)| msg.type_ == *type_)
+}
+
+/// Line such as `3 users have joined, 1 user has quit`
+fn summary(run: &[&ChannelMessage]) -> String {
+    STATUS_TYPES
+        .iter()
+        .filter_map(
+            |(type_, one, many)| match run.iter().filter(|m| m.type_ == *type_).count() {
+                0 => None,
+                1 => Some(format!("1 {one}")),
+                count => Some(format!("{count} {many}")),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Entry of the chat, from the newest
+pub enum Row<'a> {
+    Message(&'a ChannelMessage, Visibility),
+    /// Consecutive status messages from the oldest, there's always more than
+    /// one
+    Condensed(Vec<&'a ChannelMessage>),
+    /// Separator above the first unread message
+    Marker,
+}
+
+impl Row<'_> {
+    /// Id of the oldest message of a condensed run, which stays the same
+    /// while newer messages join it
+    fn condensed_id(&self) -> Option<u32> {
+        match self {
+            Row::Condensed(run) => run.first().map(|m| m.id),
+            _ => None,
+        }
+    }
+
+    /// Id of the newest message in the row
+    fn newest_id(&self) -> Option<u32> {
+        match self {
+            Row::Message(m, _) => Some(m.id),
+            Row::Condensed(run) => run.last().map(|m| m.id),
+            Row::Marker => None,
+        }
+    }
+}
+
+/// Messages grouped into rows from the newest, skipping the hidden ones.
+/// Hidden messages don't break up a run of status messages.
+pub struct Rows<'a, F> {
+    messages: Peekable<Rev<slice::Iter<'a, ChannelMessage>>>,
+    visibility: F,
+    status: StatusMessages,
+    /// Id of the last read message until the marker has been passed
+    last_read: Option<u32>,
+    /// A row newer than the last read message was returned
+    unread: bool,
+}
+
+impl<'a, F: FnMut(&ChannelMessage) -> Visibility> Rows<'a, F> {
+    pub fn new(messages: &'a [ChannelMessage], visibility: F, status: StatusMessages) -> Self {
+        Self {
+            messages: messages.iter().rev().peekable(),
+            visibility,
+            status,
+            last_read: None,
+            unread: false,
+        }
+    }
+
+    /// Put the unread marker after the message with the id, as in
+    /// `first_unread` of the channel. Negative ids have no marker.
+    pub fn marker(mut self, first_unread: i32) -> Self {
+        self.last_read = u32::try_from(first_unread).ok();
+        self
+    }
+
+    fn is_read(&self, msg: &ChannelMessage) -> bool {
+        self.last_read.is_some_and(|last_read| msg.id <= last_read)
+    }
+}
+
+impl<'a, F: FnMut(&ChannelMessage) -> Visibility> Iterator for Rows<'a, F> {
+    type Item = Row<'a>;
+
+    fn next(&mut self) -> Option<Row<'a>> {
+        loop {
+            let &m = self.messages.peek()?;
+            if self.is_read(m) {
+                self.last_read = None;
+                // Nothing is unread if the marker would be the newest row
+                if self.unread {
+                    return Some(Row::Marker);
+                }
+            }
+
+            self.messages.next();
+            let visibility = (self.visibility)(m);
+            match (visibility, self.status) {
+                (Visibility::Hidden, _) => continue,
+                (_, StatusMessages::Hidden) if is_status(m) => continue,
+                _ => {}
+            }
+            self.unread |= self.last_read.is_some();
+            if self.status != StatusMessages::Condensed || !is_status(m) {
+                return Some(Row::Message(m, visibility));
+            }
 
-use crate::types::ChannelMessage;
+            let mut run = vec![m];
+            while let Some(&next) = self.messages.peek() {
+                let hidden = (self.visibility)(next) == Visibility::Hidden;
+                // The marker splits runs
+                if !hidden && !is_status(next) || self.is_read(next) {
+                    break;
+                }
+                self.messages.next();
+                if !hidden {
+                    run.push(next);
+                }
+            }
+
+            return Some(match run.as_slice() {
+                [_] => Row::Message(m, visibility),
+                _ => {
+                    run.reverse();
+                    Row::Condensed(run)
+                }
+            });
+        }
+    }
+}
 
 pub struct ChatWidget {}
 
 impl ChatWidget {
-    pub fn ui<'a>(title: &'a str, messages: &'a [ChannelMessage]) -> List<'a> {
-        let messages: Vec<ListItem> = messages
-            .iter()
-            .map(|m| {
-                let msg = if let (Some(mode), Some(nick)) = (&m.from.mode, &m.from.nick) {
-                    format!("    {mode}{nick}")
-                } else {
-                    "    ~system~".into()
-                };
-
-                let msg = if m.type_ != "message" {
-                    format!("{msg}: {}", m.type_)
-                } else {
-                    format!("{msg}: {}", m.text)
-                };
-
-                let content = Span::raw(msg);
-                ListItem::new(content)
-            })
-            .rev()
-            .collect();
+    /// Only the newest messages that fit into the area are wrapped.
+    /// Condensed runs show their messages below the summary if they're
+    /// expanded, and the runs in view are remembered in `runs`.
+    pub fn ui<'a>(
+        title: String,
+        rows: impl Iterator<Item = Row<'a>>,
+        runs: &mut Runs,
+        cache: &mut WrapCache,
+        area: Rect,
+    ) -> List<'static> {
+        // Space inside the borders
+        let width = usize::from(area.width.saturating_sub(2));
+        let height = usize::from(area.height.saturating_sub(2));
+
+        let mut shown = 0;
+        let mut items = Vec::new();
+        runs.visible.clear();
+        for row in rows {
+            if shown >= height {
+                break;
+            }
+            runs.visible.extend(row.condensed_id());
+            let selected = runs.selected;
+            items.extend(row_items(
+                &row,
+                &runs.expanded,
+                selected,
+                cache,
+                width,
+                &mut shown,
+            ));
+        }
 
-        List::new(messages)
+        List::new(items)
             .direction(ListDirection::BottomToTop)
             .block(Block::default().borders(Borders::ALL).title(title))
     }
+
+    /// Messages to scroll so that the unread marker is near the top of the
+    /// area, with as many unread rows below it as fit. 0 if all of them fit
+    /// or there's no marker.
+    pub fn marker_scroll<'a>(
+        messages: &[ChannelMessage],
+        mut rows: impl Iterator<Item = Row<'a>>,
+        expanded: &HashSet<u32>,
+        cache: &mut WrapCache,
+        area: Rect,
+    ) -> usize {
+        let width = usize::from(area.width.saturating_sub(2));
+        let height = usize::from(area.height.saturating_sub(2));
+
+        // Newest message and height of the unread rows, from the newest
+        let mut unread = Vec::new();
+        let mut total = 0;
+        loop {
+            let row = match rows.next() {
+                Some(row) => row,
+                None => return 0,
+            };
+            let newest = match row.newest_id() {
+                Some(id) => id,
+                None => break,
+            };
+            let mut lines = 0;
+            row_items(&row, expanded, None, cache, width, &mut lines);
+            unread.push((newest, lines));
+            total += lines;
+        }
+        // The marker takes a line too
+        if total < height {
+            return 0;
+        }
+
+        // Oldest unread rows that fit below the marker, at least one
+        let (mut bottom, oldest_lines) = unread[unread.len() - 1];
+        let mut used = 1 + oldest_lines;
+        for &(newest, lines) in unread.iter().rev().skip(1) {
+            used += lines;
+            if used > height {
+                break;
+            }
+            bottom = newest;
+        }
+        messages
+            .iter()
+            .rposition(|m| m.id == bottom)
+            .map_or(0, |pos| messages.len() - 1 - pos)
+    }
+}
+
+/// List items of a row, adding their lines to `shown`. The summary of the
+/// `selected` run is highlighted.
+fn row_items(
+    row: &Row,
+    expanded: &HashSet<u32>,
+    selected: Option<u32>,
+    cache: &mut WrapCache,
+    width: usize,
+    shown: &mut usize,
+) -> Vec<ListItem<'static>> {
+    let dimmed = Style::default().add_modifier(Modifier::DIM);
+    match row {
+        Row::Message(m, visibility) => {
+            let style = match visibility {
+                Visibility::Dimmed => dimmed,
+                _ => Style::default(),
+            };
+            vec![item(&cache.get(m, width), style, shown)]
+        }
+        Row::Condensed(run) => {
+            let open = expanded.contains(&run[0].id);
+            let mut items = Vec::new();
+            if open {
+                for m in run.iter().rev() {
+                    items.push(item(&cache.get(m, width), Style::default(), shown));
+                }
+            }
+            let marker = if open { "[-]" } else { "[+]" };
+            let text = format!("{marker} {}", summary(run));
+            // Time of the newest message, as the run ends there
+            let time = time_column(&run[run.len() - 1].time, cache.zone);
+            let lines = wrap(&format!("{time} ~system~: "), &text, width);
+            let style = if selected == Some(run[0].id) {
+                dimmed.add_modifier(Modifier::REVERSED)
+            } else {
+                dimmed
+            };
+            items.push(item(&lines, style, shown));
+            items
+        }
+        Row::Marker => {
+            *shown += 1;
+            let line = Line::from("— new messages —").alignment(Alignment::Center);
+            vec![ListItem::new(line).style(Style::default().fg(Color::Red))]
+        }
+    }
+}
+
+/// List item of wrapped lines, adding their count to `shown`
+fn item(lines: &[String], style: Style, shown: &mut usize) -> ListItem<'static> {
+    *shown += lines.len();
+    let lines: Vec<Line> = lines.iter().map(|l| Line::raw(l.clone())).collect();
+    ListItem::new(Text::from(lines)).style(style)
+}
+
+fn wrap_message(msg: &ChannelMessage, width: usize, zone: TimeZone) -> Vec<String> {
+    let (prefix, text) = format_message(msg, zone);
+    let mut lines = wrap(&prefix, text, width);
+
+    // Every field of a whois reply goes on its own line under the nick
+    if let Some(whois) = &msg.whois {
+        let indent = " ".repeat(str_width(&prefix));
+        for (label, value) in whois::fields(whois) {
+            lines.extend(wrap(&indent, &format!("{label}: {value}"), width));
+        }
+    }
+
+    lines
+}
+
+/// Time and nick columns and the text of the message
+fn format_message(m: &ChannelMessage, zone: TimeZone) -> (String, &str) {
+    let time = time_column(&m.time, zone);
+    let prefix = if let (Some(mode), Some(nick)) = (&m.from.mode, &m.from.nick) {
+        format!("{time} {mode}{nick}: ")
+    } else {
+        format!("{time} ~system~: ")
+    };
+
+    if let Some(whois) = &m.whois {
+        (prefix, &whois.nick)
+    } else if m.type_ != "message" {
+        (prefix, &m.type_)
+    } else {
+        (prefix, &m.text)
+    }
+}
+
+/// Time of a message as `HH:MM`
+fn time_column(time: &str, zone: TimeZone) -> String {
+    zone.format(time, "%H:%M").unwrap_or_else(|| "--:--".into())
+}
+
+/// Word wrap the text after the prefix. Continuation lines are indented to
+/// start under the text and words longer than a line are split.
+pub fn wrap(prefix: &str, text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let prefix_width = str_width(prefix);
+    let indent = if prefix_width + MIN_TEXT_WIDTH <= width {
+        prefix_width
+    } else {
+        0
+    };
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    let mut line_width = 0;
+    push_chars(
+        &mut lines,
+        &mut line,
+        &mut line_width,
+        prefix,
+        width,
+        indent,
+    );
+    let text_start = line_width;
+
+    for word in text.split(' ') {
+        let line_start = if lines.is_empty() { text_start } else { indent };
+        let space = usize::from(line_width > line_start);
+        if line_width > indent && line_width + space + str_width(word) > width {
+            // Move the word to the next line
+            lines.push(line.trim_end().into());
+            line = " ".repeat(indent);
+            line_width = indent;
+        } else if space > 0 {
+            line.push(' ');
+            line_width += 1;
+        }
+
+        push_chars(&mut lines, &mut line, &mut line_width, word, width, indent);
+    }
+
+    lines.push(line.trim_end().into());
+    lines
+}
+
+/// Append the characters and break the line whenever it gets full
+fn push_chars(
+    lines: &mut Vec<String>,
+    line: &mut String,
+    line_width: &mut usize,
+    text: &str,
+    width: usize,
+    indent: usize,
+) {
+    for c in text.chars() {
+        let char_width = c.width().unwrap_or(0);
+        if *line_width + char_width > width && *line_width > indent {
+            lines.push(line.trim_end().into());
+            *line = " ".repeat(indent);
+            *line_width = indent;
+        }
+        line.push(c);
+        *line_width += char_width;
+    }
+}
+
+fn str_width(text: &str) -> usize {
+    text.chars().map(|c| c.width().unwrap_or(0)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::layout::Rect;
+    use serde_json::json;
+    use std::collections::HashSet;
+
+    use super::{summary, wrap, wrap_message, ChatWidget, Row, Rows, Runs, Visibility, WrapCache};
+    use crate::{config::TimeZone, tui::state::StatusMessages, types::ChannelMessage};
+
+    fn message(id: u32, type_: &str) -> ChannelMessage {
+        serde_json::from_value(json!({
+            "from": {"mode": "", "nick": "crab"}, "gecos": null, "hostmask": null, "id": id,
+            "self": false, "text": "", "time": "2024-04-20T11:00:00.000Z", "type": type_,
+        }))
+        .unwrap()
+    }
+
+    /// Ids of the rows, condensed runs in brackets
+    fn rows(messages: &[ChannelMessage], hidden: u32, status: StatusMessages) -> Vec<String> {
+        marked_rows(messages, hidden, status, -1)
+    }
+
+    fn marked_rows(
+        messages: &[ChannelMessage],
+        hidden: u32,
+        status: StatusMessages,
+        first_unread: i32,
+    ) -> Vec<String> {
+        let visibility = |m: &ChannelMessage| match m.id {
+            id if id == hidden => Visibility::Hidden,
+            _ => Visibility::Shown,
+        };
+        Rows::new(messages, visibility, status)
+            .marker(first_unread)
+            .map(|row| match row {
+                Row::Marker => "--".into(),
+                Row::Message(m, _) => m.id.to_string(),
+                Row::Condensed(run) => {
+                    let ids: Vec<String> = run.iter().map(|m| m.id.to_string()).collect();
+                    format!("[{}]", ids.join(" "))
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn status_runs_are_condensed() {
+        let types = ["join", "message", "join", "quit", "join", "message", "part"];
+        let messages: Vec<_> = (1..).zip(types).map(|(id, t)| message(id, t)).collect();
+
+        assert_eq!(
+            rows(&messages, 0, StatusMessages::Condensed),
+            ["7", "6", "[3 4 5]", "2", "1"]
+        );
+        assert_eq!(rows(&messages, 0, StatusMessages::Hidden), ["6", "2"]);
+        assert_eq!(rows(&messages, 0, StatusMessages::Shown).len(), 7);
+        // A filtered message doesn't split the run, nor count in it
+        assert_eq!(
+            rows(&messages, 6, StatusMessages::Condensed),
+            ["[3 4 5 7]", "2", "1"]
+        );
+        assert_eq!(
+            rows(&messages, 4, StatusMessages::Condensed),
+            ["7", "6", "[3 5]", "2", "1"]
+        );
+    }
+
+    #[test]
+    fn marker_is_above_first_unread() {
+        let types = ["message", "join", "join", "quit", "message"];
+        let messages: Vec<_> = (1..).zip(types).map(|(id, t)| message(id, t)).collect();
+        let condensed = StatusMessages::Condensed;
+
+        assert_eq!(
+            marked_rows(&messages, 0, condensed, 1),
+            ["5", "[2 3 4]", "--", "1"]
+        );
+        // The marker splits a run, leaving a single message
+        assert_eq!(
+            marked_rows(&messages, 0, condensed, 3),
+            ["5", "4", "--", "[2 3]", "1"]
+        );
+        // Nothing unread, or nothing visible that's unread
+        assert_eq!(marked_rows(&messages, 0, condensed, 5).len(), 3);
+        assert_eq!(marked_rows(&messages, 5, condensed, 4), ["[2 3 4]", "1"]);
+        assert_eq!(marked_rows(&messages, 0, condensed, 0).len(), 3);
+    }
+
+    #[test]
+    fn marker_scroll_keeps_oldest_unread_in_view() {
+        let messages: Vec<_> = (1..=20).map(|id| message(id, "message")).collect();
+        let scroll = |first_unread, height: u16| {
+            let rows = Rows::new(&messages, |_| Visibility::Shown, StatusMessages::Shown)
+                .marker(first_unread);
+            let area = Rect::new(0, 0, 40, height + 2);
+            ChatWidget::marker_scroll(
+                &messages,
+                rows,
+                &HashSet::new(),
+                &mut WrapCache::default(),
+                area,
+            )
+        };
+
+        // Eleven unread and the marker fit into twelve lines
+        assert_eq!(scroll(9, 12), 0);
+        // Messages 11 to 15 stay below the marker, the newer ones are scrolled
+        assert_eq!(scroll(10, 6), 5);
+        assert_eq!(scroll(20, 6), 0);
+    }
+
+    #[test]
+    fn keys_apply_to_the_selected_run_in_view() {
+        let types = [
+            "join", "join", "message", "quit", "part", "message", "nick", "join",
+        ];
+        let messages: Vec<_> = (1..).zip(types).map(|(id, t)| message(id, t)).collect();
+        let mut runs = Runs::default();
+        let draw = |runs: &mut Runs, height: u16| {
+            let rows = Rows::new(&messages, |_| Visibility::Shown, StatusMessages::Condensed);
+            let area = Rect::new(0, 0, 40, height + 2);
+            ChatWidget::ui(String::new(), rows, runs, &mut WrapCache::default(), area);
+        };
+
+        // Only the newest run fits
+        draw(&mut runs, 1);
+        runs.toggle();
+        assert_eq!(runs.expanded, HashSet::from([7]));
+        runs.toggle();
+        assert!(runs.expanded.is_empty());
+
+        draw(&mut runs, 10);
+        runs.select_next();
+        runs.toggle();
+        assert_eq!(runs.expanded, HashSet::from([4]));
+        // Wraps around to the newest run
+        runs.select_next();
+        runs.select_next();
+        runs.toggle();
+        assert_eq!(runs.expanded, HashSet::from([4, 7]));
+    }
+
+    #[test]
+    fn summary_counts_every_type() {
+        let run = [message(1, "join"), message(2, "quit"), message(3, "join")];
+        let run: Vec<_> = run.iter().collect();
+        assert_eq!(summary(&run), "2 users have joined, 1 user has quit");
+    }
+
+    #[test]
+    fn time_column_is_in_the_zone_and_indent() {
+        let mut msg = message(1, "message");
+        msg.text = "the quick brown fox jumps".into();
+        let zone = TimeZone::try_from("+02:00".to_string()).unwrap();
+        assert_eq!(
+            wrap_message(&msg, 24, zone),
+            [
+                "13:00 crab: the quick",
+                "            brown fox",
+                "            jumps"
+            ]
+        );
+    }
+
+    #[test]
+    fn short_message_is_one_line() {
+        assert_eq!(wrap("  nick: ", "hello world", 40), ["  nick: hello world"]);
+    }
+
+    #[test]
+    fn continuation_lines_align_after_nick() {
+        assert_eq!(
+            wrap("nick: ", "the quick brown fox jumps over", 20),
+            ["nick: the quick", "      brown fox", "      jumps over"]
+        );
+    }
+
+    #[test]
+    fn long_words_are_split() {
+        assert_eq!(
+            wrap("nick: ", "see https://example.com/a/long/path", 20),
+            [
+                "nick: see",
+                "      https://exampl",
+                "      e.com/a/long/p",
+                "      ath"
+            ]
+        );
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        assert_eq!(
+            wrap("n: ", "日本語のテキスト", 15),
+            ["n: 日本語のテキ", "   スト"]
+        );
+    }
+
+    #[test]
+    fn narrow_width_drops_indent() {
+        assert_eq!(
+            wrap("nick: ", "one two three", 8),
+            ["nick:", "one two", "three"]
+        );
+    }
 }
@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+
+use crate::{
+    config::{Filter, FilterAction},
+    types::ChannelMessage,
+};
+
+use super::chat::Visibility;
+
+/// Cached actions are dropped all at once after this many
+const MAX_CACHED: usize = 10_000;
+
+/// Hidden messages of a channel and the messages they were counted over
+#[derive(Default)]
+struct HiddenCount {
+    first: Option<u32>,
+    last: Option<u32>,
+    len: usize,
+    count: usize,
+}
+
+/// Filters from the config and whether the hidden messages are revealed
+pub struct Filters {
+    filters: Vec<Filter>,
+    /// Show hidden messages dimmed instead
+    pub reveal: bool,
+    /// Action of the matching filter by message id, which are unique over
+    /// all channels until the Lounge restarts
+    actions: HashMap<u32, Option<FilterAction>>,
+    /// By channel id
+    hidden: HashMap<u32, HiddenCount>,
+}
+
+impl Filters {
+    pub fn new(filters: Vec<Filter>) -> Self {
+        Self {
+            filters,
+            reveal: false,
+            actions: HashMap::new(),
+            hidden: HashMap::new(),
+        }
+    }
+
+    /// Forget the cached actions and counts, e.g. when message ids were
+    /// reused after the Lounge restarted
+    pub fn clear(&mut self) {
+        self.actions.clear();
+        self.hidden.clear();
+    }
+
+    /// Action of the first filter matching the message
+    fn action(&mut self, channel: &str, msg: &ChannelMessage) -> Option<FilterAction> {
+        if self.actions.len() >= MAX_CACHED {
+            self.actions.clear();
+        }
+
+        let filters = &self.filters;
+        *self.actions.entry(msg.id).or_insert_with(|| {
+            filters
+                .iter()
+                .find(|filter| matches(filter, channel, msg))
+                .map(|filter| filter.action)
+        })
+    }
+
+    /// Hidden by a filter, even if it's revealed right now
+    fn is_hidden(&mut self, channel: &str, msg: &ChannelMessage) -> bool {
+        self.action(channel, msg) == Some(FilterAction::Hide)
+    }
+
+    pub fn visibility(&mut self, channel: &str, msg: &ChannelMessage) -> Visibility {
+        match self.action(channel, msg) {
+            None => Visibility::Shown,
+            Some(FilterAction::Hide) if !self.reveal => Visibility::Hidden,
+            Some(_) => Visibility::Dimmed,
+        }
+    }
+
+    /// Messages of the channel hidden by a filter. Only messages added after
+    /// the last call are checked, unless older ones were loaded or dropped.
+    pub fn hidden(&mut self, chan: u32, channel: &str, messages: &[ChannelMessage]) -> usize {
+        let counted = self.hidden.remove(&chan).unwrap_or_default();
+        let appended = counted.first == messages.first().map(|m| m.id)
+            && counted.len <= messages.len()
+            && counted.last == counted.len.checked_sub(1).map(|last| messages[last].id);
+
+        let (start, mut count) = if appended {
+            (counted.len, counted.count)
+        } else {
+            (0, 0)
+        };
+        for msg in &messages[start..] {
+            count += usize::from(self.is_hidden(channel, msg));
+        }
+
+        self.hidden.insert(
+            chan,
+            HiddenCount {
+                first: messages.first().map(|m| m.id),
+                last: messages.last().map(|m| m.id),
+                len: messages.len(),
+                count,
+            },
+        );
+        count
+    }
+}
+
+fn matches(filter: &Filter, channel: &str, msg: &ChannelMessage) -> bool {
+    let nick = msg.from.nick.as_deref().unwrap_or_default();
+
+    (filter.channels.is_empty()
+        || filter
+            .channels
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(channel)))
+        && (filter.types.is_empty() || filter.types.contains(&msg.type_))
+        && filter.nick.as_ref().is_none_or(|glob| glob.matches(nick))
+        && filter.hostmask.as_ref().is_none_or(|glob| {
+            msg.hostmask
+                .as_ref()
+                .is_some_and(|host| glob.matches(&format!("{nick}!{host}")))
+        })
+        && filter
+            .text
+            .as_ref()
+            .is_none_or(|regex| regex.is_match(&msg.text))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Filters;
+    use crate::{config::Config, tui::chat::Visibility, types::ChannelMessage};
+
+    fn message(id: u32, nick: &str, hostmask: &str, type_: &str, text: &str) -> ChannelMessage {
+        serde_json::from_value(json!({
+            "from": {"mode": "", "nick": nick}, "gecos": null, "hostmask": hostmask, "id": id,
+            "self": false, "text": text, "time": "2024-04-20T11:00:00.000Z", "type": type_,
+        }))
+        .unwrap()
+    }
+
+    fn filters() -> Filters {
+        let config: Config = toml::from_str(
+            r##"
+            [[filters]]
+            types = ["join", "part", "quit"]
+            channels = ["#rust"]
+
+            [[filters]]
+            nick = "*bot"
+            text = '^\[CI\]'
+            action = "dim"
+
+            [[filters]]
+            hostmask = "*!*@*.spam.example"
+            "##,
+        )
+        .unwrap();
+        Filters::new(config.filters)
+    }
+
+    #[test]
+    fn conditions_must_all_match() {
+        let mut filters = filters();
+        let join = message(1, "crab", "~crab@reef", "join", "");
+        assert_eq!(filters.visibility("#rust", &join), Visibility::Hidden);
+        let join = message(2, "crab", "~crab@reef", "join", "");
+        assert_eq!(filters.visibility("#offtopic", &join), Visibility::Shown);
+
+        let ci = message(3, "GitBot", "~git@ci", "message", "[CI] build passed");
+        assert_eq!(filters.visibility("#rust", &ci), Visibility::Dimmed);
+        let chat = message(4, "GitBot", "~git@ci", "message", "hello");
+        assert_eq!(filters.visibility("#rust", &chat), Visibility::Shown);
+
+        let spam = message(5, "x", "~y@host.spam.example", "message", "buy");
+        assert_eq!(filters.visibility("#debian", &spam), Visibility::Hidden);
+    }
+
+    #[test]
+    fn revealed_messages_are_dimmed() {
+        let mut filters = filters();
+        filters.reveal = true;
+        let join = message(1, "crab", "~crab@reef", "join", "");
+        assert_eq!(filters.visibility("#rust", &join), Visibility::Dimmed);
+        assert!(filters.is_hidden("#rust", &join));
+    }
+
+    #[test]
+    fn hidden_count_follows_messages() {
+        let mut filters = filters();
+        let mut messages: Vec<_> = (10..14)
+            .map(|id| message(id, "crab", "~crab@reef", "join", ""))
+            .collect();
+        assert_eq!(filters.hidden(2, "#rust", &messages), 4);
+
+        messages.push(message(14, "crab", "~crab@reef", "message", "hi"));
+        messages.push(message(15, "crab", "~crab@reef", "quit", ""));
+        assert_eq!(filters.hidden(2, "#rust", &messages), 5);
+
+        // Older history and trimmed messages are counted again
+        messages.insert(0, message(9, "crab", "~crab@reef", "part", ""));
+        assert_eq!(filters.hidden(2, "#rust", &messages), 6);
+        messages.drain(..3);
+        assert_eq!(filters.hidden(2, "#rust", &messages), 3);
+        messages.clear();
+        assert_eq!(filters.hidden(2, "#rust", &messages), 0);
+    }
+
+    #[test]
+    fn reused_ids_are_checked_again() {
+        let mut filters = filters();
+        let join = message(1, "crab", "~crab@reef", "join", "");
+        assert_eq!(filters.visibility("#rust", &join), Visibility::Hidden);
+        assert_eq!(filters.hidden(2, "#rust", &[join]), 1);
+
+        // Same id from a restarted Lounge
+        filters.clear();
+        let chat = message(1, "crab", "~crab@reef", "message", "hi");
+        assert_eq!(filters.visibility("#rust", &chat), Visibility::Shown);
+        assert_eq!(filters.hidden(2, "#rust", &[chat]), 0);
+    }
+
+    #[test]
+    fn invalid_regex_is_a_config_error() {
+        let config = toml::from_str::<Config>("[[filters]]\ntext = '(oops'\n");
+        assert!(config.unwrap_err().to_string().contains("unclosed group"));
+    }
+}
@@ -0,0 +1,175 @@
+//! IRC formatting codes into styled text
+
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::Span,
+};
+
+const BOLD: char = '\x02';
+const COLOR: char = '\x03';
+const HEX_COLOR: char = '\x04';
+const RESET: char = '\x0f';
+const MONOSPACE: char = '\x11';
+const REVERSE: char = '\x16';
+const ITALIC: char = '\x1d';
+const STRIKETHROUGH: char = '\x1e';
+const UNDERLINE: char = '\x1f';
+
+/// Split text into spans styled by the IRC formatting codes in it
+pub fn irc_spans(text: &str) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut style = Style::default();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let modifier = match c {
+            BOLD => Some(Modifier::BOLD),
+            ITALIC => Some(Modifier::ITALIC),
+            UNDERLINE => Some(Modifier::UNDERLINED),
+            STRIKETHROUGH => Some(Modifier::CROSSED_OUT),
+            REVERSE => Some(Modifier::REVERSED),
+            _ => None,
+        };
+        let is_code = modifier.is_some() || matches!(c, COLOR | HEX_COLOR | RESET | MONOSPACE);
+        if !is_code {
+            current.push(c);
+            continue;
+        }
+
+        if !current.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut current), style));
+        }
+
+        style = match (c, modifier) {
+            (_, Some(modifier)) if style.add_modifier.contains(modifier) => Style {
+                add_modifier: style.add_modifier - modifier,
+                ..style
+            },
+            (_, Some(modifier)) => style.add_modifier(modifier),
+            (RESET, _) => Style::default(),
+            (COLOR, _) => {
+                let fg = take_number(&mut chars);
+                let bg = match (fg, chars.peek()) {
+                    (Some(_), Some(',')) => {
+                        chars.next();
+                        take_number(&mut chars)
+                    }
+                    _ => None,
+                };
+                match fg {
+                    Some(fg) => Style {
+                        fg: Some(color(fg)),
+                        bg: bg.map(color).or(style.bg),
+                        ..style
+                    },
+                    // Color code without a number resets the colors
+                    None => Style {
+                        fg: None,
+                        bg: None,
+                        ..style
+                    },
+                }
+            }
+            (HEX_COLOR, _) => {
+                // Hex colors aren't supported, skip them
+                for _ in 0..6 {
+                    chars.next_if(|c| c.is_ascii_hexdigit());
+                }
+                style
+            }
+            _ => style,
+        };
+    }
+
+    if !current.is_empty() {
+        spans.push(Span::styled(current, style));
+    }
+    spans
+}
+
+/// Color number of at most two digits
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<u8> {
+    let first = chars.next_if(char::is_ascii_digit)?;
+    let mut number = first.to_digit(10)? as u8;
+    if let Some(second) = chars.next_if(char::is_ascii_digit) {
+        number = number * 10 + second.to_digit(10)? as u8;
+    }
+    Some(number)
+}
+
+/// The 16 standard IRC colors
+fn color(number: u8) -> Color {
+    match number {
+        0 => Color::White,
+        1 => Color::Black,
+        2 => Color::Blue,
+        3 => Color::Green,
+        4 => Color::LightRed,
+        5 => Color::Red,
+        6 => Color::Magenta,
+        7 => Color::Yellow,
+        8 => Color::LightYellow,
+        9 => Color::LightGreen,
+        10 => Color::Cyan,
+        11 => Color::LightCyan,
+        12 => Color::LightBlue,
+        13 => Color::LightMagenta,
+        14 => Color::DarkGray,
+        15 => Color::Gray,
+        _ => Color::Reset,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::{
+        style::{Color, Modifier, Style},
+        text::Span,
+    };
+
+    use super::irc_spans;
+
+    #[test]
+    fn plain_text_is_one_span() {
+        assert_eq!(irc_spans("hello"), [Span::raw("hello")]);
+    }
+
+    #[test]
+    fn modifiers_toggle() {
+        assert_eq!(
+            irc_spans("a\x02bold\x02 \x1ditalic\x0f"),
+            [
+                Span::raw("a"),
+                Span::styled("bold", Style::default().add_modifier(Modifier::BOLD)),
+                Span::raw(" "),
+                Span::styled("italic", Style::default().add_modifier(Modifier::ITALIC)),
+            ]
+        );
+    }
+
+    #[test]
+    fn colors() {
+        assert_eq!(
+            irc_spans("\x034,12red on blue\x03 \x0399x"),
+            [
+                Span::styled(
+                    "red on blue",
+                    Style::default().fg(Color::LightRed).bg(Color::LightBlue)
+                ),
+                Span::raw(" "),
+                Span::styled("x", Style::default().fg(Color::Reset)),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_after_color_codes() {
+        // Only two digits belong to the color
+        assert_eq!(
+            irc_spans("\x03031st"),
+            [Span::styled("1st", Style::default().fg(Color::Green))]
+        );
+        assert_eq!(irc_spans("\x04ff0000hex"), [Span::raw("hex")]);
+    }
+}
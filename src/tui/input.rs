@@ -3,7 +3,7 @@ use ratatui::widgets::{Block, Borders, Paragraph};
 pub struct InputWidget {}
 
 impl InputWidget {
-    pub fn ui(input: &str) -> Paragraph {
+    pub fn ui(input: &str) -> Paragraph<'_> {
         Paragraph::new(input).block(Block::default().borders(Borders::ALL).title("Input"))
     }
 }
@@ -0,0 +1,44 @@
+use ratatui::{
+    text::Span,
+    widgets::{Block, Borders, List, ListDirection, ListItem},
+};
+
+/// Lines of a log file opened in the viewer
+pub struct LogView {
+    pub title: String,
+    pub lines: Vec<String>,
+    /// How many lines the view is scrolled up from the bottom
+    pub scroll: usize,
+}
+
+impl LogView {
+    pub fn scroll_up(&mut self, amount: usize) {
+        self.scroll = (self.scroll + amount).min(self.lines.len().saturating_sub(1));
+    }
+
+    pub fn scroll_down(&mut self, amount: usize) {
+        self.scroll = self.scroll.saturating_sub(amount);
+    }
+}
+
+pub struct LogWidget {}
+
+impl LogWidget {
+    pub fn ui(view: &LogView) -> List<'_> {
+        let lines: Vec<ListItem> = view
+            .lines
+            .iter()
+            .rev()
+            .skip(view.scroll)
+            .map(|line| ListItem::new(Span::raw(line.as_str())))
+            .collect();
+
+        List::new(lines)
+            .direction(ListDirection::BottomToTop)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(view.title.as_str()),
+            )
+    }
+}
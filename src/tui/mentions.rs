@@ -0,0 +1,111 @@
+use ratatui::{
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState},
+};
+
+use super::{format::irc_spans, state::TuiState};
+use crate::{config::TimeZone, types::Mention};
+
+/// Selection of the mentions screen
+#[derive(Default)]
+pub struct MentionList {
+    selected: usize,
+    /// Shown instead of the key help until the next key
+    pub notice: Option<&'static str>,
+    pub state: ListState,
+}
+
+impl MentionList {
+    pub fn close(&mut self) {
+        self.selected = 0;
+        self.notice = None;
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected += 1;
+    }
+
+    pub fn selected<'a>(&self, mentions: &'a [Mention]) -> Option<&'a Mention> {
+        mentions.get(self.selected.min(mentions.len().checked_sub(1)?))
+    }
+}
+
+/// Date and time of an ISO 8601 timestamp in the zone, e.g. `04-20 11:00`
+fn time(time: &str, zone: TimeZone) -> String {
+    zone.format(time, "%m-%d %H:%M")
+        .unwrap_or_else(|| time.into())
+}
+
+pub struct MentionsWidget {}
+
+impl MentionsWidget {
+    /// Mentions under headers of their network and channel, which are looked
+    /// up in `state`. Selection of `list` is clamped to the mentions, render
+    /// the widget with `list.state`.
+    pub fn ui(
+        mentions: &[Mention],
+        state: &TuiState,
+        list: &mut MentionList,
+        zone: TimeZone,
+    ) -> List<'static> {
+        if !mentions.is_empty() {
+            list.selected = list.selected.min(mentions.len() - 1);
+        }
+
+        let mut items = Vec::new();
+        let mut selected = None;
+        // Network uuid and channel id of the previous mention
+        let mut last = None;
+        for (i, mention) in mentions.iter().enumerate() {
+            let network = state.network_of(mention.chan_id);
+            let uuid = network.map(|n| n.uuid.as_str());
+            if last.map(|(uuid, _)| uuid) != Some(uuid) {
+                let name = network.map_or("Closed channels", |n| &n.name);
+                items.push(ListItem::new(Line::from(name.to_string()).bold()));
+            }
+            if last.map(|(_, chan)| chan) != Some(mention.chan_id) {
+                if let Some(channel) = state.channel(mention.chan_id) {
+                    items.push(ListItem::new(
+                        Line::from(format!("  {}", channel.name)).bold(),
+                    ));
+                }
+            }
+            last = Some((uuid, mention.chan_id));
+
+            if i == list.selected {
+                selected = Some(items.len());
+            }
+            let nick = mention.from.nick.as_deref().unwrap_or_default();
+            let mut line = vec![
+                Span::raw(format!("    {} ", time(&mention.time, zone))),
+                Span::raw(format!("<{nick}> ")).bold(),
+            ];
+            line.extend(irc_spans(&mention.text));
+            items.push(ListItem::new(Line::from(line)));
+        }
+        list.state.select(selected);
+
+        let title = if mentions.is_empty() {
+            "Mentions (none)"
+        } else {
+            "Mentions"
+        };
+        let help = match list.notice {
+            Some(notice) => Line::from(notice).fg(Color::Yellow),
+            None => Line::from("enter jump  d dismiss  D dismiss all  esc close"),
+        };
+        List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(title)
+                    .title_bottom(help),
+            )
+            .highlight_style(Style::default().reversed())
+    }
+}
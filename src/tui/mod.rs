@@ -4,23 +4,54 @@ use crossterm::{
     ExecutableCommand,
 };
 use ratatui::{
-    backend::CrosstermBackend,
-    layout::{Constraint, Layout},
-    widgets::{Block, Borders, Paragraph, Widget},
+    backend::{Backend, CrosstermBackend},
+    layout::{Constraint, Layout, Rect},
+    widgets::{Block, Borders, Clear, Paragraph, Widget},
     Frame, Terminal,
 };
-use std::io::{self, stdout};
+use std::{
+    io::{self, stdout, Write},
+    iter, mem,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    config::{Config, StatusConfig, TimeZone},
+    events::{Login, SocketError},
+};
 
 use self::{
-    channels::ChannelsWidget, chat::ChatWidget, input::InputWidget, state::TuiState,
-    users::UsersWidget,
+    channels::ChannelsWidget,
+    chat::{ChatWidget, Rows, Runs, WrapCache},
+    filters::Filters,
+    input::InputWidget,
+    logview::{LogView, LogWidget},
+    mentions::{MentionList, MentionsWidget},
+    sessions::{SessionList, SessionsWidget},
+    state::TuiState,
+    status::StatusWidget,
+    topic::TopicWidget,
+    users::{UserList, UsersWidget},
+    whois::WhoisWidget,
 };
 
 mod channels;
 mod chat;
+mod filters;
+mod format;
 mod input;
+mod logview;
+mod mentions;
+mod sessions;
 mod state;
+mod status;
+#[cfg(test)]
+mod tests;
+mod topic;
 mod users;
+mod whois;
 
 struct Buffer {
     text: String,
@@ -40,11 +71,34 @@ impl Buffer {
         self.pos = 0;
     }
 
+    /// Replace the text and move the cursor to the end
+    fn set(&mut self, text: String) {
+        self.pos = text.chars().count();
+        self.text = text;
+    }
+
     fn add(&mut self, c: char) {
         self.text.insert(self.pos, c);
         self.pos += 1;
     }
 
+    /// Insert text at the cursor, separated from the text around it
+    fn insert(&mut self, text: &str) {
+        let before = self.text.chars().nth(self.pos.wrapping_sub(1));
+        if before.is_some_and(|c| !c.is_whitespace()) {
+            self.add(' ');
+        }
+        text.chars().for_each(|c| self.add(c));
+        if self
+            .text
+            .chars()
+            .nth(self.pos)
+            .is_some_and(|c| !c.is_whitespace())
+        {
+            self.add(' ');
+        }
+    }
+
     fn next(&mut self) {
         if self.pos < self.text.chars().count() {
             self.pos += 1;
@@ -57,6 +111,24 @@ impl Buffer {
         }
     }
 
+    /// Word the cursor is in or right after, without the punctuation of e.g.
+    /// `nick: hello`
+    fn word_at_cursor(&self) -> Option<&str> {
+        let pos = self
+            .text
+            .char_indices()
+            .nth(self.pos)
+            .map_or(self.text.len(), |(i, _)| i);
+        let start = self.text[..pos]
+            .rfind(char::is_whitespace)
+            .map_or(0, |i| i + 1);
+        let end = self.text[pos..]
+            .find(char::is_whitespace)
+            .map_or(self.text.len(), |i| pos + i);
+        let word = self.text[start..end].trim_matches(|c: char| ",:;.!?@".contains(c));
+        (!word.is_empty()).then_some(word)
+    }
+
     fn backspace(&mut self) {
         if !self.text.is_empty() {
             self.pos -= 1;
@@ -70,15 +142,267 @@ struct TuiApp {
     state: TuiState,
     show_users: bool,
     show_channels: bool,
+    /// Log file or command output shown instead of the chat
+    log_view: Option<LogView>,
+    /// Chat messages wrapped to the width of the chat pane
+    wrap_cache: WrapCache,
+    /// Filter and selection of the user list
+    user_list: UserList,
+    /// Show the whole topic instead of a single line
+    expand_topic: bool,
+    /// Selection of the sessions screen
+    session_list: SessionList,
+    /// Selection of the mentions screen
+    mention_list: MentionList,
+    /// Messages hidden or dimmed by the config
+    filters: Filters,
+    /// Condensed runs of status messages shown in full or selected
+    runs: Runs,
+    /// Channel that was active when the chat was drawn last
+    drawn_channel: u32,
+    /// Scroll to the unread marker once the channel has its history
+    to_marker: bool,
+    /// Format of the status bar, see `StatusConfig`
+    status_format: String,
+    /// Time shown by the clock of the status bar
+    now: fn() -> SystemTime,
+    /// Zone of the clock and the message times
+    timezone: TimeZone,
+    /// Inits of the state that the caches were filled after
+    inits: usize,
 }
 
 impl TuiApp {
-    pub fn new() -> Self {
+    pub fn new(login: Login, config: Config) -> Result<Self, SocketError> {
+        let mut app = Self::with_state(TuiState::new(login, &config)?);
+        app.status_format = config.status.format;
+        app.filters = Filters::new(config.filters);
+        app.wrap_cache = WrapCache::new(config.timezone);
+        app.timezone = config.timezone;
+        Ok(app)
+    }
+
+    fn with_state(state: TuiState) -> Self {
         Self {
-            state: TuiState::new(),
+            state,
             input_buffer: Buffer::new(),
             show_users: false,
             show_channels: false,
+            log_view: None,
+            wrap_cache: WrapCache::default(),
+            user_list: UserList::default(),
+            expand_topic: false,
+            session_list: SessionList::default(),
+            mention_list: MentionList::default(),
+            filters: Filters::new(Vec::new()),
+            runs: Runs::default(),
+            drawn_channel: 0,
+            to_marker: false,
+            status_format: StatusConfig::default().format,
+            now: SystemTime::now,
+            timezone: TimeZone::Local,
+            inits: 0,
+        }
+    }
+
+    /// Handle the queued server events. Returns true if anything changed.
+    fn update(&mut self) -> bool {
+        let changed = self.state.update();
+        if self.state.inits() != self.inits {
+            // Message ids may belong to other messages after a reconnect
+            self.inits = self.state.inits();
+            self.wrap_cache.clear();
+            self.filters.clear();
+            self.runs = Runs::default();
+        }
+        changed
+    }
+
+    /// Nick to whois from the chat: the other side of a query, a nick in the
+    /// input under the cursor, or else the sender of the newest message
+    fn whois_target(&self) -> Option<String> {
+        let channel = self.state.channel(self.state.active())?;
+        if channel.type_ == "query" {
+            return Some(channel.name.clone());
+        }
+
+        let word = self.input_buffer.word_at_cursor();
+        let user = word.and_then(|word| {
+            channel
+                .users
+                .iter()
+                .filter_map(|user| user.nick.as_deref())
+                .find(|nick| nick.eq_ignore_ascii_case(word))
+        });
+        let sender = || {
+            channel
+                .messages
+                .iter()
+                .rev()
+                .filter(|msg| {
+                    !msg.self_ && ["message", "action", "notice"].contains(&msg.type_.as_str())
+                })
+                .find_map(|msg| msg.from.nick.as_deref())
+        };
+        user.or_else(sender).map(String::from)
+    }
+
+    /// Open the log of the active channel
+    fn open_log(&mut self) {
+        self.log_view = self.state.active_log().map(|(path, lines)| LogView {
+            title: path.display().to_string(),
+            lines,
+            scroll: 0,
+        });
+    }
+
+    /// Scroll a newly opened channel to its unread marker, or any channel
+    /// once asked to. Waits until the channel isn't fetching its history.
+    fn scroll_to_marker(&mut self, area: Rect) {
+        let active = self.state.active();
+        if mem::replace(&mut self.drawn_channel, active) != active {
+            self.to_marker = true;
+        }
+        let channel = match self.state.channel(active) {
+            Some(channel) if self.to_marker && !channel.fetching => channel,
+            _ => return,
+        };
+        self.to_marker = false;
+
+        let topic = if channel.topic.is_empty() {
+            0
+        } else {
+            TopicWidget::height(&channel.topic, self.expand_topic, area.width)
+        };
+        let area = Rect {
+            height: area.height.saturating_sub(topic),
+            ..area
+        };
+        let rows = Rows::new(
+            &channel.messages,
+            |m| self.filters.visibility(&channel.name, m),
+            self.state.status_messages(active),
+        )
+        .marker(channel.first_unread);
+        let scroll = ChatWidget::marker_scroll(
+            &channel.messages,
+            rows,
+            &self.runs.expanded,
+            &mut self.wrap_cache,
+            area,
+        );
+        self.state.scroll_to(scroll);
+    }
+
+    /// Keys of the focused user list. Typing filters the list.
+    fn user_list_key(&mut self, key: KeyEvent) {
+        let users = self
+            .state
+            .channel(self.state.active())
+            .map_or(&[][..], |c| &c.users);
+
+        match key.code {
+            KeyCode::Esc => self.user_list.close(),
+            KeyCode::Up => self.user_list.select_prev(1),
+            KeyCode::Down => self.user_list.select_next(1),
+            KeyCode::PageUp => self.user_list.select_prev(10),
+            KeyCode::PageDown => self.user_list.select_next(10),
+            KeyCode::Backspace => self.user_list.pop_filter(),
+            KeyCode::Enter => {
+                if let Some(nick) = self.user_list.selected(users).and_then(|u| u.nick.clone()) {
+                    self.user_list.close();
+                    self.state
+                        .handle_input(&format!("/query {nick}"), self.state.active());
+                }
+            }
+            KeyCode::Char(c) => self.user_list.push_filter(c),
+            _ => {}
+        }
+    }
+
+    /// Show the output of a command, if there's any
+    fn open_view(&mut self) {
+        if let Some((title, lines)) = self.state.take_view() {
+            self.log_view = Some(LogView {
+                title,
+                lines,
+                scroll: 0,
+            });
+        }
+    }
+
+    /// Put the URL of a finished upload into the input. Returns true while
+    /// an upload is running or when one has finished.
+    fn poll_upload(&mut self) -> bool {
+        if !self.state.poll_upload() {
+            return self.state.upload().is_some();
+        }
+
+        if let Some(url) = self.state.take_uploaded() {
+            self.input_buffer.insert(&url);
+        }
+        self.open_view();
+        true
+    }
+
+    /// Keys of the sessions screen
+    fn sessions_key(&mut self, key: KeyEvent) {
+        let sessions = self.state.sessions().unwrap_or_default();
+
+        match key.code {
+            KeyCode::Esc => {
+                self.session_list.close();
+                self.state.close_sessions();
+            }
+            KeyCode::Up => self.session_list.select_prev(),
+            KeyCode::Down => self.session_list.select_next(),
+            KeyCode::Char(' ') => self.session_list.toggle_mark(sessions),
+            KeyCode::Enter => {
+                let tokens = self.session_list.tokens(sessions);
+                self.state.sign_out(&tokens);
+                self.session_list.close();
+            }
+            KeyCode::Char('o') => {
+                self.state.sign_out_others();
+                self.session_list.close();
+            }
+            _ => {}
+        }
+    }
+
+    /// Keys of the mentions screen
+    fn mentions_key(&mut self, key: KeyEvent) {
+        let mentions = self.state.mentions().unwrap_or_default();
+        let selected = self
+            .mention_list
+            .selected(mentions)
+            .map(|m| (m.chan_id, m.msg_id));
+
+        self.mention_list.notice = None;
+        match (key.code, selected) {
+            (KeyCode::Esc, _) => {
+                self.mention_list.close();
+                self.state.close_mentions();
+            }
+            // Mentions only know the id of their channel, so there's no
+            // name to join it again by
+            (KeyCode::Enter, Some((chan, _))) if self.state.channel(chan).is_none() => {
+                self.mention_list.notice = Some("channel was closed, can't jump to it");
+            }
+            (KeyCode::Up, _) => self.mention_list.select_prev(),
+            (KeyCode::Down, _) => self.mention_list.select_next(),
+            (KeyCode::Enter, Some((chan, msg_id))) => {
+                self.mention_list.close();
+                self.state.jump_to_mention(chan, msg_id);
+                // The mention is scrolled to instead of the unread marker
+                self.drawn_channel = self.state.active();
+                self.to_marker = false;
+            }
+            (KeyCode::Char('d') | KeyCode::Delete, Some((_, msg_id))) => {
+                self.state.dismiss_mention(msg_id)
+            }
+            (KeyCode::Char('D'), _) => self.state.dismiss_all_mentions(),
+            _ => {}
         }
     }
 
@@ -87,12 +411,89 @@ impl TuiApp {
             return;
         }
 
-        if key.modifiers.contains(KeyModifiers::ALT) {
+        if self.state.whois().is_some() {
+            if matches!(key.code, KeyCode::Esc | KeyCode::Enter) {
+                self.state.close_whois();
+            }
+            return;
+        }
+
+        if self.state.sessions().is_some() {
+            self.sessions_key(key);
+            return;
+        }
+
+        if self.state.mentions().is_some() {
+            self.mentions_key(key);
+            return;
+        }
+
+        if let Some(view) = &mut self.log_view {
+            match key.code {
+                KeyCode::Esc => self.log_view = None,
+                KeyCode::Char('l') if key.modifiers.contains(KeyModifiers::ALT) => {
+                    self.log_view = None
+                }
+                KeyCode::Up => view.scroll_up(1),
+                KeyCode::Down => view.scroll_down(1),
+                KeyCode::PageUp => view.scroll_up(10),
+                KeyCode::PageDown => view.scroll_down(10),
+                _ => {}
+            }
+            return;
+        }
+
+        if self.user_list.focused && key.modifiers.contains(KeyModifiers::ALT) {
+            if key.code == KeyCode::Char('i') {
+                let users = self
+                    .state
+                    .channel(self.state.active())
+                    .map_or(&[][..], |c| &c.users);
+                if let Some(nick) = self.user_list.selected(users).and_then(|u| u.nick.clone()) {
+                    self.state.request_whois(&nick);
+                }
+                return;
+            }
+        } else if self.user_list.focused {
+            self.user_list_key(key);
+            return;
+        }
+
+        if key
+            .modifiers
+            .contains(KeyModifiers::ALT | KeyModifiers::SHIFT)
+        {
+            match key.code {
+                KeyCode::Up => self.state.move_channel_up(),
+                KeyCode::Down => self.state.move_channel_down(),
+                KeyCode::Char('C') => self.runs.toggle(),
+                _ => {}
+            }
+        } else if key.modifiers.contains(KeyModifiers::ALT) {
             match key.code {
                 KeyCode::Up => self.state.prev_channel(),
                 KeyCode::Down => self.state.next_channel(),
                 KeyCode::Char('b') => self.show_channels = !self.show_channels,
-                KeyCode::Char('v') => self.show_users = !self.show_users,
+                KeyCode::Char('v') => {
+                    self.show_users = !self.show_users;
+                    self.user_list.close();
+                }
+                KeyCode::Char('w') => self.state.close_active(),
+                KeyCode::Char('m') => self.state.toggle_mute(),
+                KeyCode::Char('l') => self.open_log(),
+                KeyCode::Char('i') => {
+                    if let Some(nick) = self.whois_target() {
+                        self.state.request_whois(&nick);
+                    }
+                }
+                KeyCode::Char('t') => self.expand_topic = !self.expand_topic,
+                KeyCode::Char('f') => self.filters.reveal = !self.filters.reveal,
+                KeyCode::Char('c') => self.runs.select_next(),
+                KeyCode::Char('n') => self.to_marker = true,
+                KeyCode::Char('u') => {
+                    self.show_users = true;
+                    self.user_list.focused = true;
+                }
                 _ => {}
             }
         } else {
@@ -107,10 +508,22 @@ impl TuiApp {
                 KeyCode::Right => {
                     self.input_buffer.next();
                 }
+                KeyCode::PageUp => self.state.scroll_up(10),
+                KeyCode::PageDown => self.state.scroll_down(10),
                 KeyCode::Enter => {
                     self.state
                         .handle_input(&self.input_buffer.text, self.state.active());
                     self.input_buffer.clear();
+
+                    if let Some(text) = self.state.take_prefill() {
+                        self.input_buffer.set(text);
+                    }
+                    self.open_view();
+                }
+                KeyCode::Tab => {
+                    if let Some(text) = self.state.complete(&self.input_buffer.text) {
+                        self.input_buffer.set(text);
+                    }
                 }
                 _ => {}
             }
@@ -118,42 +531,151 @@ impl TuiApp {
     }
 }
 
-pub fn run_tui() -> io::Result<()> {
+/// Shortest time between two redraws
+const FRAME_TIME: Duration = Duration::from_millis(16);
+
+/// How often the progress of an upload is redrawn
+const UPLOAD_REFRESH: Duration = Duration::from_millis(200);
+
+/// Reasons for the main loop to wake up
+enum Wakeup {
+    /// Key press, resize or other input from the terminal
+    Terminal(Event),
+    /// New events from the server are waiting in the queue
+    Server,
+}
+
+pub fn run_tui(login: Login, config: Config) -> io::Result<()> {
+    // Connect first, so that an error isn't hidden by the alternate screen
+    let mut app = TuiApp::new(login, config)
+        .map_err(|err| io::Error::other(format!("connection failed: {err}")))?;
+
     enable_raw_mode()?;
     stdout().execute(EnterAlternateScreen)?;
     let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
 
-    let mut should_quit = false;
-    let mut app = TuiApp::new();
-
-    while !should_quit {
-        terminal.draw(|frame| ui(frame, &app))?;
-        should_quit = handle_events(&mut app)?;
+    let (tx, rx) = mpsc::channel();
+    {
+        let tx = tx.clone();
+        app.state.set_waker(Box::new(move || {
+            let _ = tx.send(Wakeup::Server);
+        }));
     }
+    thread::spawn(move || read_terminal(tx));
+
+    let result = main_loop(&mut terminal, &mut app, &rx);
 
     disable_raw_mode()?;
     stdout().execute(LeaveAlternateScreen)?;
 
-    Ok(())
+    result
+}
+
+/// Forward terminal events to the main loop
+fn read_terminal(tx: Sender<Wakeup>) {
+    while let Ok(event) = event::read() {
+        if tx.send(Wakeup::Terminal(event)).is_err() {
+            break;
+        }
+    }
 }
 
-fn handle_events(app: &mut TuiApp) -> io::Result<bool> {
-    if event::poll(std::time::Duration::from_millis(50))? {
-        if let Event::Key(key) = event::read()? {
-            if key.kind == event::KeyEventKind::Press
-                && key.modifiers.contains(KeyModifiers::CONTROL)
-                && key.code == KeyCode::Char('q')
-            {
-                return Ok(true);
+/// Sleep until there's input or server events and redraw only when something
+/// has changed, at most once per `FRAME_TIME`
+fn main_loop<B: Backend>(
+    terminal: &mut Terminal<B>,
+    app: &mut TuiApp,
+    rx: &Receiver<Wakeup>,
+) -> io::Result<()> {
+    let mut dirty = true;
+    let mut last_draw: Option<Instant> = None;
+    let has_clock = app.status_format.contains("{clock}");
+    let mut minute = 0;
+
+    loop {
+        dirty |= app.state.run_scheduled();
+
+        // Wake up for the next delayed alias line
+        let mut timeout = app
+            .state
+            .next_scheduled()
+            .map(|at| at.saturating_duration_since(Instant::now()));
+
+        // Redraw the clock of the status bar when the minute changes
+        if has_clock {
+            let seconds = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            dirty |= seconds / 60 != minute;
+            minute = seconds / 60;
+            let next_minute = Duration::from_secs(60 - seconds % 60);
+            timeout = Some(timeout.map_or(next_minute, |timeout| timeout.min(next_minute)));
+        }
+
+        if app.poll_upload() {
+            dirty = true;
+            timeout = Some(timeout.map_or(UPLOAD_REFRESH, |t| t.min(UPLOAD_REFRESH)));
+        }
+        if dirty {
+            let since_draw = last_draw.map_or(FRAME_TIME, |t| t.elapsed());
+            if since_draw >= FRAME_TIME {
+                terminal.draw(|frame| ui(frame, app))?;
+                last_draw = Some(Instant::now());
+                dirty = false;
+            } else {
+                let frame = FRAME_TIME - since_draw;
+                timeout = Some(timeout.map_or(frame, |timeout| timeout.min(frame)));
+            }
+        }
+
+        let wakeup = match timeout {
+            Some(timeout) => match rx.recv_timeout(timeout) {
+                Ok(wakeup) => wakeup,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            },
+            None => match rx.recv() {
+                Ok(wakeup) => wakeup,
+                Err(_) => return Ok(()),
+            },
+        };
+
+        // Handle everything that piled up before drawing again
+        for wakeup in iter::once(wakeup).chain(rx.try_iter()) {
+            match wakeup {
+                Wakeup::Terminal(event) => {
+                    if handle_terminal_event(app, event) {
+                        return Ok(());
+                    }
+                    dirty = true;
+                }
+                Wakeup::Server => dirty |= app.update(),
             }
+        }
 
-            app.key_event(key)
+        if app.state.take_notification() {
+            // Ring the terminal bell
+            stdout().write_all(b"\x07")?;
+            stdout().flush()?;
         }
     }
+}
+
+/// Returns true if the app should quit
+fn handle_terminal_event(app: &mut TuiApp, event: Event) -> bool {
+    if let Event::Key(key) = event {
+        if key.kind == event::KeyEventKind::Press
+            && key.modifiers.contains(KeyModifiers::CONTROL)
+            && key.code == KeyCode::Char('q')
+        {
+            return true;
+        }
 
-    app.state.update();
+        app.key_event(key);
+        return app.state.quit_requested();
+    }
 
-    Ok(false)
+    false
 }
 
 fn tmp_area(name: &str) -> impl Widget {
@@ -164,7 +686,7 @@ fn tmp_area(name: &str) -> impl Widget {
     )
 }
 
-fn ui(frame: &mut Frame, app: &TuiApp) {
+fn ui(frame: &mut Frame, app: &mut TuiApp) {
     let channelw = if app.show_channels { 10 } else { 0 };
 
     let usersw = if app.show_users { 10 } else { 0 };
@@ -183,6 +705,13 @@ fn ui(frame: &mut Frame, app: &TuiApp) {
     let vertical = Layout::vertical([Constraint::Percentage(80), Constraint::Percentage(20)]);
     let [channels, chat, members] = horizontal.areas(frame.size());
     let [messages, input] = vertical.areas(chat);
+    let [messages, status] =
+        Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(messages);
+
+    frame.render_widget(
+        StatusWidget::ui(&app.status_format, &app.state, (app.now)(), app.timezone),
+        status,
+    );
 
     if app.show_channels {
         frame.render_widget(
@@ -191,10 +720,50 @@ fn ui(frame: &mut Frame, app: &TuiApp) {
         );
     }
 
+    if let Some(view) = &app.log_view {
+        frame.render_widget(LogWidget::ui(view), messages);
+    }
+
+    if app.log_view.is_none() {
+        app.scroll_to_marker(messages);
+    }
+
     if let Some(channel) = app.state.channel(app.state.active()) {
-        frame.render_widget(ChatWidget::ui(&channel.name, &channel.messages), messages);
+        if app.log_view.is_none() {
+            let messages = if channel.topic.is_empty() {
+                messages
+            } else {
+                let height = TopicWidget::height(&channel.topic, app.expand_topic, messages.width);
+                let [topic, messages] =
+                    Layout::vertical([Constraint::Length(height), Constraint::Fill(1)])
+                        .areas(messages);
+                frame.render_widget(TopicWidget::ui(&channel.topic, app.expand_topic), topic);
+                messages
+            };
+
+            let filters = &mut app.filters;
+            let hidden = filters.hidden(channel.id, &channel.name, &channel.messages);
+            let title = match hidden {
+                0 => channel.name.clone(),
+                _ if filters.reveal => format!("{} ({hidden} filtered, revealed)", channel.name),
+                _ => format!("{} ({hidden} hidden)", channel.name),
+            };
+
+            let shown = channel.messages.len().saturating_sub(app.state.scroll());
+            let rows = Rows::new(
+                &channel.messages[..shown],
+                |m| filters.visibility(&channel.name, m),
+                app.state.status_messages(channel.id),
+            )
+            .marker(channel.first_unread);
+            frame.render_widget(
+                ChatWidget::ui(title, rows, &mut app.runs, &mut app.wrap_cache, messages),
+                messages,
+            );
+        }
         if app.show_users {
-            frame.render_widget(UsersWidget::ui(&channel.users), members);
+            let widget = UsersWidget::ui(&channel.users, &mut app.user_list);
+            frame.render_stateful_widget(widget, members, &mut app.user_list.state);
         }
     } else {
         frame.render_widget(tmp_area("messages"), messages);
@@ -203,4 +772,22 @@ fn ui(frame: &mut Frame, app: &TuiApp) {
 
     frame.render_widget(InputWidget::ui(&app.input_buffer.text), input);
     frame.set_cursor(input.x + 1 + app.input_buffer.pos as u16, input.y + 1);
+
+    if let Some(sessions) = app.state.sessions() {
+        let widget = SessionsWidget::ui(sessions, &mut app.session_list, (app.now)());
+        frame.render_widget(Clear, messages);
+        frame.render_stateful_widget(widget, messages, &mut app.session_list.state);
+    }
+
+    if let Some(mentions) = app.state.mentions() {
+        let widget = MentionsWidget::ui(mentions, &app.state, &mut app.mention_list, app.timezone);
+        frame.render_widget(Clear, messages);
+        frame.render_stateful_widget(widget, messages, &mut app.mention_list.state);
+    }
+
+    if let Some(whois) = app.state.whois() {
+        let area = WhoisWidget::area(whois, frame.size());
+        frame.render_widget(Clear, area);
+        frame.render_widget(WhoisWidget::ui(whois), area);
+    }
 }
@@ -0,0 +1,129 @@
+use ratatui::{
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::{Block, Borders, List, ListItem, ListState},
+};
+use std::{
+    collections::BTreeSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::types::Session;
+
+/// Selection and marks of the sessions screen
+#[derive(Default)]
+pub struct SessionList {
+    selected: usize,
+    /// Tokens of the sessions marked for signing out
+    marked: BTreeSet<String>,
+    pub state: ListState,
+}
+
+impl SessionList {
+    pub fn close(&mut self) {
+        self.selected = 0;
+        self.marked.clear();
+    }
+
+    pub fn select_prev(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected += 1;
+    }
+
+    fn selected<'a>(&self, sessions: &'a [Session]) -> Option<&'a Session> {
+        sessions.get(self.selected.min(sessions.len().checked_sub(1)?))
+    }
+
+    /// Mark the selected session, or unmark it if it's marked
+    pub fn toggle_mark(&mut self, sessions: &[Session]) {
+        if let Some(session) = self.selected(sessions) {
+            if !self.marked.remove(&session.token) {
+                self.marked.insert(session.token.clone());
+            }
+        }
+    }
+
+    /// Tokens of the marked sessions, or of the selected one if none are
+    /// marked
+    pub fn tokens(&self, sessions: &[Session]) -> Vec<String> {
+        let marked: Vec<String> = sessions
+            .iter()
+            .filter(|s| self.marked.contains(&s.token))
+            .map(|s| s.token.clone())
+            .collect();
+
+        if marked.is_empty() {
+            self.selected(sessions)
+                .map(|s| s.token.clone())
+                .into_iter()
+                .collect()
+        } else {
+            marked
+        }
+    }
+}
+
+/// Time since the last use in the largest unit, e.g. `3h ago`
+fn last_use(session: &Session, now: SystemTime) -> String {
+    if session.active {
+        return "active now".into();
+    }
+
+    let now = now.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis());
+    let seconds = now.saturating_sub(u128::from(session.last_use)) / 1000;
+    let units = [(86400, "d"), (3600, "h"), (60, "m")];
+    match units.iter().find(|(size, _)| seconds >= *size) {
+        Some((size, unit)) => format!("{}{unit} ago", seconds / size),
+        None => "just now".into(),
+    }
+}
+
+pub struct SessionsWidget {}
+
+impl SessionsWidget {
+    /// Selection of `list` is clamped to the sessions, render the widget
+    /// with `list.state`
+    pub fn ui<'a>(sessions: &'a [Session], list: &mut SessionList, now: SystemTime) -> List<'a> {
+        if !sessions.is_empty() {
+            list.selected = list.selected.min(sessions.len() - 1);
+        }
+        list.state.select(Some(list.selected));
+
+        let items: Vec<ListItem> = sessions
+            .iter()
+            .map(|session| {
+                let mark = if list.marked.contains(&session.token) {
+                    "[x] "
+                } else {
+                    "[ ] "
+                };
+                let mut line = vec![
+                    Span::raw(mark),
+                    Span::raw(session.agent.clone()).bold(),
+                    Span::raw(format!("  {}  {}", session.ip, last_use(session, now))),
+                ];
+                if session.current {
+                    line.push(Span::raw("  (this client)").fg(Color::Green));
+                }
+                ListItem::new(Line::from(line))
+            })
+            .collect();
+
+        let title = if sessions.is_empty() {
+            "Sessions (loading)"
+        } else {
+            "Sessions"
+        };
+        List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(title)
+                    .title_bottom("space mark  enter sign out  o sign out others  esc close"),
+            )
+            .highlight_style(Style::default().reversed())
+    }
+}
@@ -1,10 +1,53 @@
 use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io, mem,
+    path::PathBuf,
+    time::Instant,
+};
+
+use self::{
+    aliases::{Step, Vars},
+    settings::Settings,
+};
+
+pub use crate::config::StatusMessages;
+
+mod aliases;
+mod commands;
+mod settings;
+
+/// Older messages are fetched once less than this many are left above the
+/// scrolled view
+const FETCH_MARGIN: usize = 50;
+
+/// Newer messages left below a mention that was jumped to
+const MENTION_CONTEXT: usize = 5;
 
 use crate::{
-    events::{Event, IrcEvents},
-    types::{Init, Join, More, Msg, Names, Network, NetworkChannel},
+    config::{BufferConfig, Config},
+    events::{Event, IrcEvents, Login, SocketError, Transport, Waker},
+    logger::Logger,
+    token::TokenFile,
+    types::{
+        ChannelMessage, Configuration, Init, Join, Mention, More, Msg, MuteChanged, Names, Network,
+        NetworkChannel, Part, Quit, Session, SyncSort, Topic, Whois,
+    },
+    upload::{self, Upload},
 };
 
+/// State of the connection to the Lounge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connection {
+    Connecting,
+    Connected,
+    Disconnected,
+    /// Server rejected the credentials
+    LoginFailed,
+    /// Session was revoked from another client
+    SignedOut,
+}
+
 pub struct TuiState {
     /// All available networks
     networks: Vec<Network>,
@@ -14,21 +57,140 @@ pub struct TuiState {
     network_idx: usize,
     /// index into channel inside of network
     channel_idx: usize,
+    /// Position of every channel as (network index, channel index), by channel id
+    index: HashMap<u32, (usize, usize)>,
     /// List of events
-    events: IrcEvents,
+    events: Box<dyn Transport>,
+    /// Set when a message should notify the user
+    notify: bool,
+    /// Writes received messages to disk if logging is enabled
+    logger: Option<Logger>,
+    /// Limits for the messages kept in memory
+    buffer: BufferConfig,
+    /// Messages of the active channel hidden below the chat view
+    scroll: usize,
+    /// Nick of a query opened with `/query` or `/msg` that should be focused
+    /// once the server creates it
+    pending_query: Option<String>,
+    /// Nick of a `/whois` that should be shown in a popup once answered
+    pending_whois: Option<String>,
+    /// Whois reply shown in a popup
+    whois: Option<Whois>,
+    /// Lines of the aliases from the config and `/alias`, by name without
+    /// the slash
+    aliases: BTreeMap<String, Vec<String>>,
+    /// Alias lines waiting for their delay, with the time they're due and
+    /// their target
+    scheduled: VecDeque<(Instant, String, u32)>,
+    /// Commands the server handles, from the `commands` event
+    server_commands: Vec<String>,
+    /// Output of a command waiting to be shown
+    view: Option<(String, Vec<String>)>,
+    /// Set by `/quit`
+    quit: bool,
+    /// Text a command wants to put into the input for editing
+    prefill: Option<String>,
+    connection: Connection,
+    /// Logged in clients, while the sessions screen is open
+    sessions: Option<Vec<Session>>,
+    /// Address of the Lounge instance, for uploads
+    url: String,
+    /// Settings of the Lounge instance
+    configuration: Configuration,
+    /// File of an `/upload` waiting for its token
+    pending_upload: Option<PathBuf>,
+    upload: Option<Upload>,
+    /// URL of a finished upload to put into the input
+    uploaded: Option<String>,
+    /// Client settings synced through the Lounge
+    synced: Settings,
+    /// Channels that show status messages differently than the synced
+    /// setting, by network and channel name like in the config
+    status_messages: BTreeMap<String, BTreeMap<String, StatusMessages>>,
+    /// Config file that `/statusmessages` saves into
+    config_path: Option<PathBuf>,
+    /// Highlights shown on the mentions screen, None if it's closed
+    mentions: Option<Vec<Mention>>,
+    /// Channel and message id of a mention to scroll to once the history
+    /// around it is loaded
+    pending_mention: Option<(u32, u32)>,
+    /// Channel that was opened last. Its unread marker stays in place until
+    /// another channel is opened.
+    opened: u32,
+    /// Where the session token is kept between runs
+    token_file: Option<TokenFile>,
+    /// Number of `init` events handled so far
+    inits: usize,
 }
 
 impl TuiState {
-    pub fn new() -> Self {
+    /// Connect to the Lounge, returning an error if it's unreachable
+    pub fn new(login: Login, config: &Config) -> Result<Self, SocketError> {
+        let url = login.url.clone();
+        let token_file = TokenFile::for_login(&login);
+        let login = Login {
+            token: token_file.as_ref().and_then(TokenFile::load),
+            ..login
+        };
+        let mut state = Self::with_transport(Box::new(IrcEvents::connect(login)?), config);
+        state.url = url;
+        state.token_file = token_file;
+        state.config_path = Config::path();
+        Ok(state)
+    }
+
+    pub fn with_transport(events: Box<dyn Transport>, config: &Config) -> Self {
         Self {
             networks: Vec::new(),
-            events: IrcEvents::new(),
+            events,
             active: 0,
             network_idx: 0,
             channel_idx: 0,
+            index: HashMap::new(),
+            notify: false,
+            logger: Logger::new(&config.log),
+            buffer: config.buffer,
+            scroll: 0,
+            pending_query: None,
+            pending_whois: None,
+            whois: None,
+            aliases: config
+                .aliases
+                .iter()
+                .map(|(name, alias)| (name.clone(), alias.lines()))
+                .collect(),
+            scheduled: VecDeque::new(),
+            server_commands: Vec::new(),
+            view: None,
+            quit: false,
+            prefill: None,
+            connection: Connection::Connecting,
+            sessions: None,
+            url: String::new(),
+            configuration: Configuration::default(),
+            pending_upload: None,
+            upload: None,
+            uploaded: None,
+            synced: Settings::default(),
+            status_messages: config.status_messages.clone(),
+            config_path: None,
+            mentions: None,
+            pending_mention: None,
+            opened: 0,
+            token_file: None,
+            inits: 0,
         }
     }
 
+    /// State built from an `init` payload without connecting anywhere
+    #[cfg(test)]
+    pub fn from_init(init: Init) -> Self {
+        let transport = Box::new(crate::events::FakeTransport::default());
+        let mut state = Self::with_transport(transport, &Config::default());
+        state.on_init(init);
+        state
+    }
+
     pub fn networks(&self) -> &[Network] {
         &self.networks
     }
@@ -37,69 +199,579 @@ impl TuiState {
         self.active
     }
 
-    pub fn channel(&self, channel: u32) -> Option<&NetworkChannel> {
-        for network in &self.networks {
-            for chan in &network.channels {
-                if chan.id == channel {
-                    return Some(chan);
-                }
+    /// Network of the active channel
+    pub fn active_network(&self) -> Option<&Network> {
+        self.networks.get(self.network_idx)
+    }
+
+    pub fn connection(&self) -> Connection {
+        self.connection
+    }
+
+    /// Number of `init` events handled so far. Message ids restart from 1
+    /// when the Lounge server restarts, so anything keyed by them is stale
+    /// once this changes.
+    pub fn inits(&self) -> usize {
+        self.inits
+    }
+
+    /// How status messages are shown in the channel
+    pub fn status_messages(&self, chan: u32) -> StatusMessages {
+        let channel = self.channel(chan).zip(self.network_of(chan));
+        channel
+            .and_then(|(channel, network)| {
+                self.status_messages.get(&network.name)?.get(&channel.name)
+            })
+            .copied()
+            .unwrap_or_else(|| self.synced.status_messages())
+    }
+
+    /// Messages of the active channel hidden below the chat view
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    /// Scroll the active channel towards older messages, fetching them from
+    /// the server when getting close to the oldest one in memory
+    pub fn scroll_up(&mut self, lines: usize) {
+        let active = self.active;
+        let scroll = self.scroll + lines;
+        let channel = match self.channel_mut(active) {
+            Some(channel) => channel,
+            None => return,
+        };
+
+        let len = channel.messages.len();
+        let scroll = scroll.min(len.saturating_sub(1));
+        let fetch = len - scroll <= FETCH_MARGIN
+            && len < channel.total_messages as usize
+            && !channel.fetching;
+        // -1 asks for the newest messages if nothing is left
+        let last_id = channel.messages.first().map_or(-1, |m| i64::from(m.id));
+        channel.fetching |= fetch;
+        self.scroll = scroll;
+
+        if fetch {
+            self.emit(
+                "more",
+                json!({"target": active, "lastId": last_id, "condensed": false}),
+            );
+        }
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
+    /// Scroll the active channel so that this many messages are below the
+    /// view
+    pub fn scroll_to(&mut self, scroll: usize) {
+        if scroll > self.scroll {
+            self.scroll_up(scroll - self.scroll);
+        } else {
+            self.scroll_down(self.scroll - scroll);
+        }
+    }
+
+    /// Path and lines of the newest log file of the active channel
+    pub fn active_log(&self) -> Option<(PathBuf, Vec<String>)> {
+        let logger = self.logger.as_ref()?;
+        let network = self.networks.get(self.network_idx)?;
+        let channel = network.channel(self.active)?;
+        let path = logger.latest(&network.name, &channel.name)?;
+        let lines = logger.read(&path).ok()?;
+        Some((path, lines))
+    }
+
+    /// Write a batch of history of a channel into its log
+    fn log_messages(&mut self, chan: u32, messages: &[ChannelMessage]) {
+        self.with_logger(chan, |logger, network, channel| {
+            logger.log(network, channel, messages)
+        });
+    }
+
+    /// Run `log` with the network and channel name of `chan`, if logging is on
+    fn with_logger(
+        &mut self,
+        chan: u32,
+        log: impl FnOnce(&mut Logger, &str, &str) -> io::Result<()>,
+    ) {
+        let logger = match &mut self.logger {
+            Some(logger) => logger,
+            None => return,
+        };
+
+        if let Some(&(network_idx, channel_idx)) = self.index.get(&chan) {
+            let network = &self.networks[network_idx];
+            let channel = &network.channels[channel_idx];
+            // Failing to log shouldn't take down the whole client
+            let _ = log(logger, &network.name, &channel.name);
+        }
+    }
+
+    /// Whois reply to show in a popup
+    pub fn whois(&self) -> Option<&Whois> {
+        self.whois.as_ref()
+    }
+
+    pub fn close_whois(&mut self) {
+        self.whois = None;
+    }
+
+    /// Ask the server about the user and show the reply in a popup
+    pub fn request_whois(&mut self, nick: &str) {
+        self.handle_input(&format!("/whois {nick}"), self.active);
+    }
+
+    /// Sessions shown on the sessions screen, None if it's closed
+    pub fn sessions(&self) -> Option<&[Session]> {
+        self.sessions.as_deref()
+    }
+
+    /// Open the sessions screen and ask the server for the sessions
+    pub fn open_sessions(&mut self) {
+        self.sessions = Some(Vec::new());
+        self.emit("sessions:get", json!(null));
+    }
+
+    pub fn close_sessions(&mut self) {
+        self.sessions = None;
+    }
+
+    /// Revoke the sessions with the tokens. Signing out this client quits it.
+    pub fn sign_out(&mut self, tokens: &[String]) {
+        let sessions = self.sessions.as_deref().unwrap_or_default();
+        let current = sessions.iter().find(|s| s.current).map(|s| s.token.clone());
+
+        // The server drops the connection of the current session right away,
+        // so it goes last
+        for token in tokens.iter().filter(|t| Some(*t) != current.as_ref()) {
+            self.emit("sign-out", json!(token));
+        }
+        if current.is_some_and(|current| tokens.contains(&current)) {
+            self.emit("sign-out", json!(null));
+            self.forget_token();
+            self.quit = true;
+        }
+    }
+
+    /// The token of a signed out session can't log in anymore
+    fn forget_token(&mut self) {
+        if let Some(file) = self.token_file.take() {
+            let _ = file.remove();
+        }
+    }
+
+    /// Revoke every session but the one of this client
+    pub fn sign_out_others(&mut self) {
+        let others: Vec<String> = self
+            .sessions
+            .iter()
+            .flatten()
+            .filter(|s| !s.current)
+            .map(|s| s.token.clone())
+            .collect();
+        self.sign_out(&others);
+    }
+
+    /// Highlights shown on the mentions screen, None if it's closed. They're
+    /// ordered like the channels.
+    pub fn mentions(&self) -> Option<&[Mention]> {
+        self.mentions.as_deref()
+    }
+
+    /// Open the mentions screen and ask the server for the mentions
+    pub fn open_mentions(&mut self) {
+        self.mentions = Some(Vec::new());
+        self.emit("mentions:get", json!(null));
+    }
+
+    pub fn close_mentions(&mut self) {
+        self.mentions = None;
+    }
+
+    fn on_mentions(&mut self, mut mentions: Vec<Mention>) {
+        // Only wanted while the screen is open
+        if self.mentions.is_none() {
+            return;
+        }
+
+        // Mentions of closed channels go last
+        mentions.sort_by_cached_key(|m| {
+            let position = self.index.get(&m.chan_id).copied();
+            (position.is_none(), position, m.time.clone())
+        });
+        self.mentions = Some(mentions);
+    }
+
+    /// Remove the mention here and on the server
+    pub fn dismiss_mention(&mut self, msg_id: u32) {
+        if let Some(mentions) = &mut self.mentions {
+            mentions.retain(|m| m.msg_id != msg_id);
+        }
+        self.emit("mentions:dismiss", json!(msg_id));
+    }
+
+    pub fn dismiss_all_mentions(&mut self) {
+        if let Some(mentions) = &mut self.mentions {
+            mentions.clear();
+        }
+        self.emit("mentions:dismiss_all", json!(null));
+    }
+
+    /// Close the mentions screen and focus the channel of the mention,
+    /// scrolling up to it once its history is loaded
+    pub fn jump_to_mention(&mut self, chan: u32, msg_id: u32) {
+        if self.channel(chan).is_none() {
+            return;
+        }
+        self.mentions = None;
+        if self.active != chan {
+            self.focus(chan);
+        }
+        self.pending_mention = Some((chan, msg_id));
+        self.scroll_to_mention();
+    }
+
+    /// Scroll to the pending mention if it's loaded, or fetch older messages
+    /// until it is
+    fn scroll_to_mention(&mut self) {
+        let (chan, msg_id) = match self.pending_mention {
+            // Another channel was opened meanwhile
+            Some((chan, _)) if chan != self.active => {
+                self.pending_mention = None;
+                return;
+            }
+            Some(pending) => pending,
+            None => return,
+        };
+        let channel = match self.channel_mut(chan) {
+            Some(channel) => channel,
+            None => return,
+        };
+
+        let len = channel.messages.len();
+        if let Some(pos) = channel.messages.iter().position(|m| m.id == msg_id) {
+            self.pending_mention = None;
+            self.scroll = (len - 1 - pos).saturating_sub(MENTION_CONTEXT);
+            return;
+        }
+
+        // Wait for the messages already being fetched
+        if channel.fetching {
+            return;
+        }
+        let oldest = channel.messages.first().map(|m| m.id);
+        if oldest.is_some_and(|oldest| oldest < msg_id) || len >= channel.total_messages as usize {
+            // The message isn't there anymore
+            self.pending_mention = None;
+            return;
+        }
+
+        channel.fetching = true;
+        self.scroll = len.saturating_sub(1);
+        let last_id = oldest.map_or(-1, i64::from);
+        self.emit(
+            "more",
+            json!({"target": chan, "lastId": last_id, "condensed": false}),
+        );
+    }
+
+    /// Check the file and ask the server for an upload token. The upload
+    /// starts once the token arrives.
+    fn request_upload(&mut self, path: &str) {
+        let path = upload::expand_home(path);
+        let error = match path.metadata() {
+            _ if self.upload.is_some() || self.pending_upload.is_some() => {
+                Some("Another upload is still running".into())
+            }
+            _ if !self.configuration.file_upload => {
+                Some("Uploads are disabled on the server".into())
+            }
+            Err(err) => Some(format!("{}: {err}", path.display())),
+            Ok(meta) if meta.is_dir() => Some(format!("{} is a directory", path.display())),
+            Ok(meta) => match u64::try_from(self.configuration.file_upload_max_file_size) {
+                Ok(max) if meta.len() > max => Some(format!(
+                    "{} is {}, the server allows at most {}",
+                    path.display(),
+                    upload::format_size(meta.len()),
+                    upload::format_size(max),
+                )),
+                _ => None,
+            },
+        };
+
+        match error {
+            Some(error) => self.show("Upload", vec![error]),
+            None => {
+                self.pending_upload = Some(path);
+                self.emit("upload:auth", json!(null));
             }
         }
-        None
     }
 
-    pub fn handle_input(&mut self, input: &str, target: u32) {
-        if !self.handle_commad(input) {
-            self.events.emit_input(input, target);
+    fn on_upload_auth(&mut self, token: String) {
+        if let Some(path) = self.pending_upload.take() {
+            match Upload::start(&self.url, &token, &path) {
+                Ok(upload) => self.upload = Some(upload),
+                Err(error) => self.show("Upload", vec![error]),
+            }
         }
     }
 
-    /// Check if input has a command that can be handled on the client side.
-    /// returns true if command is handled on the client side.
-    fn handle_commad(&mut self, input: &str) -> bool {
+    /// Running upload
+    pub fn upload(&self) -> Option<&Upload> {
+        self.upload.as_ref()
+    }
+
+    /// Check if the running upload is done. Returns true if it is.
+    pub fn poll_upload(&mut self) -> bool {
+        let result = match self.upload.as_ref().and_then(Upload::poll) {
+            Some(result) => result,
+            None => return false,
+        };
+
+        self.upload = None;
+        match result {
+            Ok(url) => self.uploaded = Some(url),
+            Err(error) => self.show("Upload failed", vec![error]),
+        }
+        true
+    }
+
+    /// URL of a finished upload
+    pub fn take_uploaded(&mut self) -> Option<String> {
+        self.uploaded.take()
+    }
+
+    /// Returns true once for every message that should notify the user
+    pub fn take_notification(&mut self) -> bool {
+        std::mem::take(&mut self.notify)
+    }
+
+    pub fn channel(&self, channel: u32) -> Option<&NetworkChannel> {
+        let (network_idx, channel_idx) = *self.index.get(&channel)?;
+        self.networks[network_idx].channels.get(channel_idx)
+    }
+
+    /// Network the channel belongs to
+    pub fn network_of(&self, channel: u32) -> Option<&Network> {
+        let (network_idx, _) = *self.index.get(&channel)?;
+        self.networks.get(network_idx)
+    }
+
+    pub fn handle_input(&mut self, input: &str, target: u32) {
         let inputs: Vec<&str> = input.split_whitespace().collect();
-        if inputs[0] == "/join" && inputs.len() >= 2 {
-            // check if we already are in the channel and jump into it
-            let name = inputs[1];
-            if let Some(channel) = self.networks[self.network_idx]
-                .channels
-                .iter()
-                .find(|c| c.name == name)
-            {
-                self.active = channel.id;
-                self.set_selected();
-                self.update_active();
-                return true;
+        let (name, args) = match inputs.split_first() {
+            Some((cmd, args)) if cmd.starts_with('/') => (&cmd[1..], args),
+            Some(_) => return self.emit_input(input, target),
+            None => return,
+        };
+
+        match self.aliases.get(name) {
+            Some(lines) => match aliases::expand(lines, args, &self.alias_vars()) {
+                Ok(steps) => self.run_alias(steps, target),
+                Err(err) => self.show("Alias", vec![format!("/{name}: {err}")]),
+            },
+            None => self.handle_command(input, target),
+        }
+    }
+
+    /// Values of `$nick`, `$chan` and `$network` in aliases
+    fn alias_vars(&self) -> Vars<'_> {
+        let network = self.networks.get(self.network_idx);
+        Vars {
+            nick: network.map_or("", |n| &n.nick),
+            chan: self.channel(self.active).map_or("", |c| &c.name),
+            network: network.map_or("", |n| &n.name),
+        }
+    }
+
+    /// Run the lines of an alias. Lines after a delay are queued and run by
+    /// `run_scheduled`. Aliases aren't expanded again so they can't loop.
+    fn run_alias(&mut self, steps: Vec<Step>, target: u32) {
+        let mut at = self.scheduled.back().map(|(at, _, _)| *at);
+        for step in steps {
+            match step {
+                Step::Delay(delay) => at = Some(at.unwrap_or_else(Instant::now) + delay),
+                Step::Line(line) => match at {
+                    Some(at) => self.scheduled.push_back((at, line, target)),
+                    None => self.handle_line(&line, target),
+                },
+            }
+        }
+    }
+
+    /// Input that isn't a command is sent as a message
+    fn handle_line(&mut self, input: &str, target: u32) {
+        if input.starts_with('/') {
+            self.handle_command(input, target);
+        } else {
+            self.emit_input(input, target);
+        }
+    }
+
+    /// When the next delayed alias line should run
+    pub fn next_scheduled(&self) -> Option<Instant> {
+        self.scheduled.front().map(|(at, _, _)| *at)
+    }
+
+    /// Run the delayed alias lines that are due. Returns true if any did.
+    pub fn run_scheduled(&mut self) -> bool {
+        let now = Instant::now();
+        let mut ran = false;
+        while self.scheduled.front().is_some_and(|(at, _, _)| *at <= now) {
+            if let Some((_, line, target)) = self.scheduled.pop_front() {
+                self.handle_line(&line, target);
+                ran = true;
             }
         }
+        ran
+    }
+
+    /// Run a client side command or send it to the server if the client
+    /// doesn't handle it
+    fn handle_command(&mut self, input: &str, target: u32) {
+        let inputs: Vec<&str> = input.split_whitespace().collect();
+        let handled = match inputs.split_first() {
+            Some((cmd, args)) => cmd
+                .strip_prefix('/')
+                .and_then(commands::find)
+                .is_some_and(|command| command.run(self, args)),
+            None => true,
+        };
+
+        if !handled {
+            self.emit_input(input, target);
+        }
+    }
+
+    /// Complete the last word of the input
+    pub fn complete(&self, input: &str) -> Option<String> {
+        commands::complete(self, input)
+    }
+
+    /// Lines to show in a view, e.g. the output of `/help`
+    fn show(&mut self, title: &str, lines: Vec<String>) {
+        self.view = Some((title.into(), lines));
+    }
+
+    /// Output of a command that should be shown to the user
+    pub fn take_view(&mut self) -> Option<(String, Vec<String>)> {
+        self.view.take()
+    }
+
+    /// Text to put into the input, e.g. the topic to edit after `/topic`
+    pub fn take_prefill(&mut self) -> Option<String> {
+        self.prefill.take()
+    }
+
+    /// True once `/quit` has been used
+    pub fn quit_requested(&self) -> bool {
+        self.quit
+    }
+
+    /// Make the channel active
+    fn focus(&mut self, channel: u32) {
+        self.active = channel;
+        self.set_selected();
+        self.update_active();
+    }
+
+    /// Mute or unmute the named channels in the active network, or the active
+    /// channel if no names are given
+    fn mute_channels(&self, names: &[&str], mute: bool) {
+        let targets: Vec<u32> = if names.is_empty() {
+            self.channel(self.active)
+                .map(|c| c.id)
+                .into_iter()
+                .collect()
+        } else {
+            self.networks
+                .get(self.network_idx)
+                .into_iter()
+                .flat_map(|n| &n.channels)
+                .filter(|c| names.contains(&c.name.as_str()))
+                .map(|c| c.id)
+                .collect()
+        };
+
+        for target in targets {
+            self.emit("mute:change", json!({"target": target, "setMutedTo": mute}));
+        }
+    }
+
+    /// Toggle the mute status of the active channel
+    pub fn toggle_mute(&self) {
+        if let Some(channel) = self.channel(self.active) {
+            self.mute_channels(&[], !channel.muted);
+        }
+    }
 
-        false
+    /// Send an event to the server. A failed emit needs no handling here, it
+    /// comes back as a `Disconnected` event.
+    fn emit(&self, event: &str, data: serde_json::Value) {
+        let _ = self.events.emit(event, data);
+    }
+
+    fn emit_input(&self, input: &str, target: u32) {
+        let _ = self.events.emit_input(input, target);
     }
 
     fn channel_mut(&mut self, channel: u32) -> Option<&mut NetworkChannel> {
-        for network in &mut self.networks {
-            for chan in &mut network.channels {
-                if chan.id == channel {
-                    return Some(chan);
-                }
+        let (network_idx, channel_idx) = *self.index.get(&channel)?;
+        self.networks[network_idx].channels.get_mut(channel_idx)
+    }
+
+    /// Rebuild the channel index after channels have been added, removed or
+    /// reordered, and make sure the selection still points to a channel
+    fn reindex(&mut self) {
+        self.index.clear();
+        for (network_idx, network) in self.networks.iter().enumerate() {
+            for (channel_idx, channel) in network.channels.iter().enumerate() {
+                self.index.insert(channel.id, (network_idx, channel_idx));
             }
         }
-        None
+
+        self.set_selected();
     }
 
     fn update_active(&mut self) {
-        let channel = &mut self.networks[self.network_idx].channels[self.channel_idx];
+        if let Some(channel) = self
+            .networks
+            .get(self.network_idx)
+            .and_then(|n| n.channels.get(self.channel_idx))
+        {
+            let previous = mem::replace(&mut self.opened, channel.id);
+            if previous != channel.id {
+                self.mark_read(previous);
+            }
+        }
+
+        let channel = match self
+            .networks
+            .get_mut(self.network_idx)
+            .and_then(|n| n.channels.get_mut(self.channel_idx))
+        {
+            Some(channel) => channel,
+            None => return,
+        };
         self.active = channel.id;
-        self.events.emit("open", self.active.to_string());
+        self.scroll = 0;
+        // Server marks the channel as read once it's opened
+        channel.unread = 0;
+        channel.highlight = 0;
+        let _ = self.events.emit("open", json!(self.active));
         if !channel.loaded {
             if channel.type_ == "channel" {
-                self.events.emit("names", json!({"target": self.active}));
+                request_channel_info(&*self.events, channel);
             }
 
             // TODO: Handle showInActive case in messages
             if let Some(msg) = channel.messages.last() {
                 let last_msg_id = msg.id;
-                self.events.emit(
+                channel.fetching = true;
+                let _ = self.events.emit(
                     "more",
                     json!({"target": self.active, "lastId": last_msg_id, "condensed": false}),
                 );
@@ -107,92 +779,358 @@ impl TuiState {
 
             channel.loaded = true;
         }
+
+        // The channel that was active before may be over the limits now
+        self.trim_buffers();
+    }
+
+    /// Move the unread marker of a channel that was left below its newest
+    /// message, like the server did when the channel was opened
+    fn mark_read(&mut self, chan: u32) {
+        if let Some(channel) = self.channel_mut(chan) {
+            if let Some(last) = channel.messages.last() {
+                channel.first_unread = i32::try_from(last.id).unwrap_or(i32::MAX);
+            }
+        }
+    }
+
+    /// Drop the oldest messages of inactive channels that are over the per
+    /// channel limit, then of the largest inactive channels until all the
+    /// channels together fit in the total limit
+    fn trim_buffers(&mut self) {
+        let max = self.buffer.channel_messages;
+        let mut total = 0;
+        for channel in self.networks.iter_mut().flat_map(|n| &mut n.channels) {
+            if channel.id != self.active && channel.messages.len() > max {
+                let excess = channel.messages.len() - max;
+                channel.messages.drain(..excess);
+            }
+            total += channel.messages.len();
+        }
+
+        while total > self.buffer.total_messages {
+            let largest = self
+                .networks
+                .iter_mut()
+                .flat_map(|n| &mut n.channels)
+                .filter(|c| c.id != self.active)
+                .max_by_key(|c| c.messages.len());
+            let channel = match largest {
+                Some(channel) if !channel.messages.is_empty() => channel,
+                // Only the active channel is left
+                _ => return,
+            };
+
+            // Take at most half of a channel at a time to spread the cuts
+            let len = channel.messages.len();
+            let excess = (total - self.buffer.total_messages).min(len.div_ceil(2));
+            channel.messages.drain(..excess);
+            total -= excess;
+        }
     }
 
     pub fn prev_channel(&mut self) {
-        if self.channel_idx == 0 {
-            if self.network_idx != 0 {
-                self.network_idx -= 1;
-                self.channel_idx = self.networks[self.network_idx].channels.len() - 1;
+        let mut network_idx = self.network_idx;
+        let mut channel_idx = self.channel_idx;
+
+        // Networks without channels are skipped
+        while channel_idx == 0 {
+            if network_idx == 0 {
+                return;
             }
-        } else {
-            self.channel_idx -= 1;
+            network_idx -= 1;
+            channel_idx = self.networks[network_idx].channels.len();
         }
 
+        self.network_idx = network_idx;
+        self.channel_idx = channel_idx - 1;
         self.update_active();
     }
 
     pub fn next_channel(&mut self) {
-        if self.channel_idx >= self.networks[self.network_idx].channels.len() - 1 {
-            if self.network_idx < self.networks.len() - 1 {
-                self.network_idx += 1;
-                self.channel_idx = 0;
+        let mut network_idx = self.network_idx;
+        let mut channel_idx = self.channel_idx + 1;
+
+        // Networks without channels are skipped
+        while let Some(network) = self.networks.get(network_idx) {
+            if channel_idx < network.channels.len() {
+                self.network_idx = network_idx;
+                self.channel_idx = channel_idx;
+                self.update_active();
+                return;
             }
-        } else {
-            self.channel_idx += 1;
+            network_idx += 1;
+            channel_idx = 0;
+        }
+    }
+
+    /// Close the active channel or query. Lobbies are left alone since closing
+    /// one would remove the whole network.
+    pub fn close_active(&mut self) {
+        match self.channel(self.active) {
+            Some(channel) if channel.type_ != "lobby" => {}
+            _ => return,
         }
 
-        self.update_active();
+        // The channel is removed once the server confirms it with a part event
+        self.emit_input("/close", self.active);
     }
 
-    /// Check for new events and update state accordingly
-    pub fn update(&mut self) {
-        let event = if let Some(event) = self.events.event() {
-            event
-        } else {
+    /// Move the active channel one step up within its network
+    pub fn move_channel_up(&mut self) {
+        // Lobby is always the first channel in the network
+        if self.channel_idx <= 1 {
+            return;
+        }
+
+        if let Some(network) = self.networks.get_mut(self.network_idx) {
+            network
+                .channels
+                .swap(self.channel_idx, self.channel_idx - 1);
+            self.reindex();
+            self.emit_sort();
+        }
+    }
+
+    /// Move the active channel one step down within its network
+    pub fn move_channel_down(&mut self) {
+        let network = match self.networks.get_mut(self.network_idx) {
+            Some(network) => network,
+            None => return,
+        };
+        if self.channel_idx == 0 || self.channel_idx + 1 >= network.channels.len() {
             return;
+        }
+
+        network
+            .channels
+            .swap(self.channel_idx, self.channel_idx + 1);
+        self.reindex();
+        self.emit_sort();
+    }
+
+    /// Sync the channel order of the selected network with the server
+    fn emit_sort(&self) {
+        let network = match self.networks.get(self.network_idx) {
+            Some(network) => network,
+            None => return,
         };
+        let order: Vec<u32> = network.channels.iter().map(|c| c.id).collect();
+        self.emit(
+            "sort",
+            json!({"type": "channels", "target": network.uuid, "order": order}),
+        );
+    }
+
+    /// Select the channel at the given position, or the closest one to it
+    fn select_neighbour(&mut self, network_idx: usize, channel_idx: usize) {
+        self.network_idx = network_idx;
+        self.channel_idx = channel_idx;
+        self.clamp_selected();
+        self.update_active();
+    }
+
+    /// Move the selection to the closest channel that exists. Active is 0 if
+    /// there are no channels at the selected position.
+    fn clamp_selected(&mut self) {
+        self.network_idx = self.network_idx.min(self.networks.len().saturating_sub(1));
+        let channels = self
+            .networks
+            .get(self.network_idx)
+            .map_or(&[][..], |n| &n.channels);
+        self.channel_idx = self.channel_idx.min(channels.len().saturating_sub(1));
+        self.active = channels.get(self.channel_idx).map_or(0, |c| c.id);
+    }
+
+    /// Call `waker` from the socket thread whenever new events arrive
+    pub fn set_waker(&mut self, waker: Waker) {
+        self.events.set_waker(waker);
+    }
+
+    /// Handle all pending events and update state accordingly.
+    /// Returns true if there was anything to handle.
+    pub fn update(&mut self) -> bool {
+        let mut changed = false;
+        while let Some(event) = self.events.event() {
+            self.handle_event(event);
+            changed = true;
+        }
 
+        changed
+    }
+
+    fn handle_event(&mut self, event: Event) {
         match event {
             Event::Init(init) => self.on_init(init),
             Event::Msg(msg) => self.on_msg(msg),
             Event::More(more) => self.on_more(more),
             Event::Names(names) => self.on_names(names),
             Event::Join(join) => self.on_join(join),
+            Event::Part(part) => self.on_part(part),
+            Event::Quit(quit) => self.on_quit(quit),
+            Event::SyncSort(sort) => self.on_sync_sort(sort),
+            Event::MuteChanged(muted) => self.on_mute_changed(muted),
+            Event::Commands(commands) => self.server_commands = commands,
+            Event::Topic(topic) => self.on_topic(topic),
+            // TODO: show a login prompt
+            Event::AuthFailed => self.connection = Connection::LoginFailed,
+            Event::Connected => self.connection = Connection::Connected,
+            Event::Configuration(configuration) => self.configuration = configuration,
+            Event::UploadAuth(token) => self.on_upload_auth(token),
+            Event::SettingAll(settings) => self.synced.set_all(settings),
+            Event::SettingNew(setting) => self.synced.set(&setting.name, setting.value),
+            Event::Sessions(sessions) => {
+                // Only wanted while the screen is open
+                if self.sessions.is_some() {
+                    self.sessions = Some(sessions);
+                }
+            }
+            Event::SignedOut => {
+                self.connection = Connection::SignedOut;
+                self.forget_token();
+            }
+            Event::Mentions(mentions) => self.on_mentions(mentions),
+            Event::Disconnected => self.connection = Connection::Disconnected,
         }
     }
 
+    /// Point the selected indices to the active channel
     fn set_selected(&mut self) {
-        let mut network_idx = 0;
-        let mut channel_idx = 0;
-
-        'outer: for network in &self.networks {
-            channel_idx = 0;
-            for channel in &network.channels {
-                if self.active == channel.id {
-                    break 'outer;
-                }
-                channel_idx += 1;
+        match self.index.get(&self.active) {
+            Some(&(network_idx, channel_idx)) => {
+                self.network_idx = network_idx;
+                self.channel_idx = channel_idx;
             }
-            network_idx += 1;
+            None => self.clamp_selected(),
         }
-
-        self.network_idx = network_idx;
-        self.channel_idx = channel_idx;
     }
 
     fn on_init(&mut self, init: Init) {
+        // Init only comes after a successful login
+        self.connection = Connection::Connected;
+        self.inits += 1;
+        if let Some(file) = &self.token_file {
+            let _ = file.save(&init.token);
+        }
         self.active = init.active;
         self.networks = init.networks;
-        if let Some(channel) = self.channel_mut(self.active) {
-            channel.loaded = true;
+        if let Some(logger) = &mut self.logger {
+            for network in &self.networks {
+                for channel in &network.channels {
+                    let _ = logger.log(&network.name, &channel.name, &channel.messages);
+                }
+            }
         }
 
-        self.set_selected();
+        for channel in self.networks.iter_mut().flat_map(|n| &mut n.channels) {
+            channel.modes = history_modes(&channel.messages);
+        }
+
+        self.reindex();
+        self.opened = self.active;
+        self.emit("setting:get", json!(null));
+
+        let active = self.active;
+        if let Some(channel) = self.channel_mut(active) {
+            // Init already has the recent messages of the active channel
+            channel.loaded = true;
+        }
+        if let Some(channel) = self.channel(active) {
+            self.emit("open", json!(active));
+            if channel.type_ == "channel" {
+                request_channel_info(&*self.events, channel);
+            }
+        }
     }
 
     fn on_join(&mut self, join: Join) {
+        if let (Some(logger), Some(network)) = (
+            &mut self.logger,
+            self.networks.iter().find(|n| n.uuid == join.network),
+        ) {
+            let _ = logger.log(&network.name, &join.chan.name, &join.chan.messages);
+        }
+
+        let requested = join.chan.type_ == "query"
+            && self
+                .pending_query
+                .as_ref()
+                .is_some_and(|nick| join.chan.name.eq_ignore_ascii_case(nick));
+        if requested {
+            self.pending_query = None;
+        }
+
         if let Some(network) = self.networks.iter_mut().find(|n| n.uuid == join.network) {
-            self.active = join.chan.id;
+            if requested || join.should_open != Some(false) {
+                self.active = join.chan.id;
+            }
+
+            let mut chan = join.chan;
+            chan.modes = history_modes(&chan.messages);
+            if chan.type_ == "channel" && chan.modes.is_empty() {
+                let _ = self.events.emit_input("/mode", chan.id);
+            }
 
             if join.index >= network.channels.len() {
-                network.channels.push(join.chan);
+                network.channels.push(chan);
             } else {
-                network.channels.insert(join.index, join.chan);
+                network.channels.insert(join.index, chan);
             }
         }
 
-        self.set_selected();
+        self.reindex();
+    }
+
+    fn on_part(&mut self, part: Part) {
+        if let Some(&(network_idx, channel_idx)) = self.index.get(&part.chan) {
+            self.networks[network_idx].channels.remove(channel_idx);
+            self.reindex();
+
+            if part.chan == self.active {
+                self.select_neighbour(network_idx, channel_idx);
+            }
+        }
+    }
+
+    fn on_quit(&mut self, quit: Quit) {
+        if let Some(network_idx) = self.networks.iter().position(|n| n.uuid == quit.network) {
+            let was_active = network_idx == self.network_idx;
+            self.networks.remove(network_idx);
+            self.reindex();
+
+            if was_active {
+                self.select_neighbour(network_idx, 0);
+            }
+        }
+    }
+
+    fn on_sync_sort(&mut self, sort: SyncSort) {
+        match sort {
+            SyncSort::Networks { order } => {
+                self.networks.sort_by_key(|n| {
+                    order
+                        .iter()
+                        .position(|uuid| *uuid == n.uuid)
+                        .unwrap_or(usize::MAX)
+                });
+            }
+            SyncSort::Channels { target, order } => {
+                if let Some(network) = self.networks.iter_mut().find(|n| n.uuid == target) {
+                    // Lobby stays at the top even if it's missing from the order
+                    network.channels.sort_by_key(|c| {
+                        if c.type_ == "lobby" {
+                            return 0;
+                        }
+                        order
+                            .iter()
+                            .position(|id| *id == c.id)
+                            .map_or(usize::MAX, |p| p + 1)
+                    });
+                }
+            }
+        }
+
+        self.reindex();
     }
 
     fn on_names(&mut self, names: Names) {
@@ -201,21 +1139,1329 @@ impl TuiState {
         }
     }
 
+    fn on_topic(&mut self, topic: Topic) {
+        if let Some(channel) = self.channel_mut(topic.chan) {
+            channel.topic = topic.topic;
+        }
+    }
+
+    fn on_mute_changed(&mut self, muted: MuteChanged) {
+        let (network_idx, channel_idx) = match self.index.get(&muted.target) {
+            Some(&position) => position,
+            None => return,
+        };
+
+        let network = &mut self.networks[network_idx];
+        // Muting the lobby mutes the whole network
+        if network.channels[channel_idx].type_ == "lobby" {
+            for channel in &mut network.channels {
+                channel.muted = muted.status;
+            }
+        } else {
+            network.channels[channel_idx].muted = muted.status;
+        }
+    }
+
     fn on_msg(&mut self, msg: Msg) {
+        self.with_logger(msg.chan, |logger, network, channel| {
+            logger.append(network, channel, &msg.msg)
+        });
+
+        if let Some(whois) = &msg.msg.whois {
+            let requested = self
+                .pending_whois
+                .as_ref()
+                .is_some_and(|nick| whois.nick.eq_ignore_ascii_case(nick));
+            if requested {
+                self.pending_whois = None;
+                self.whois = Some(Whois::clone(whois));
+            }
+        }
+
+        let active = self.active;
         if let Some(channel) = self.channel_mut(msg.chan) {
+            // Private messages are always highlights
+            let private = channel.type_ == "query"
+                && !msg.msg.self_
+                && matches!(msg.msg.type_.as_str(), "message" | "action" | "notice");
+
+            if channel.id != active {
+                if let Some(unread) = msg.unread {
+                    channel.unread = unread;
+                }
+                if let Some(highlight) = msg.highlight {
+                    channel.highlight = highlight;
+                } else if private {
+                    channel.highlight += 1;
+                }
+            }
+
+            if msg.msg.type_ == "mode_channel" {
+                channel.modes = msg.msg.text.clone();
+            }
+
+            // Messages are read as they arrive in the open channel, unless
+            // the unread marker is already showing
+            let all_read = channel
+                .messages
+                .last()
+                .is_none_or(|last| i64::from(channel.first_unread) >= i64::from(last.id));
+            if channel.id == active && all_read {
+                channel.first_unread = i32::try_from(msg.msg.id).unwrap_or(i32::MAX);
+            }
+
+            let notify =
+                (msg.highlight.is_some() || private) && !channel.muted && channel.id != active;
             channel.messages.push(msg.msg);
+            channel.total_messages += 1;
+            self.notify |= notify;
+
+            if msg.chan != active {
+                self.trim_buffers();
+            } else if self.scroll > 0 {
+                // Keep the scrolled view in place
+                self.scroll += 1;
+            }
         }
     }
 
     fn on_more(&mut self, more: More) {
+        self.log_messages(more.chan, &more.messages);
+
         if let Some(channel) = self.channel_mut(more.chan) {
+            channel.fetching = false;
+            let received = more.messages.len();
             channel.messages.splice(..0, more.messages);
+
+            // Stop asking once the server runs out of history
+            channel.total_messages = match more.total_messages {
+                Some(total) if received > 0 => total,
+                _ => channel.messages.len() as u32,
+            };
         }
+        self.trim_buffers();
+        self.scroll_to_mention();
+    }
+}
+
+/// Ask for the users of a channel when it's first opened, and for its modes
+/// unless the history had them
+fn request_channel_info(events: &dyn Transport, channel: &NetworkChannel) {
+    let _ = events.emit("names", json!({"target": channel.id}));
+    if channel.modes.is_empty() {
+        // The reply comes as a `mode_channel` message
+        let _ = events.emit_input("/mode", channel.id);
     }
 }
 
+/// Modes from the newest `mode_channel` message in the history, if any
+fn history_modes(messages: &[ChannelMessage]) -> String {
+    messages
+        .iter()
+        .rfind(|m| m.type_ == "mode_channel")
+        .map(|m| m.text.clone())
+        .unwrap_or_default()
+}
+
 impl Drop for TuiState {
     fn drop(&mut self) {
-        self.events.disconnect();
+        // Nothing left to tell about a socket that's already gone
+        let _ = self.events.disconnect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use std::{
+        env, fs, process,
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{Connection, StatusMessages, TuiState};
+    use crate::{
+        config::{BufferConfig, Config},
+        events::{Event, FakeTransport, Login},
+        mock::{MockServer, Session, BASIC_SESSION},
+        token::TokenFile,
+        types::{SettingNew, Topic},
+    };
+
+    const INIT: &str = include_str!("fixtures/init.json");
+
+    /// State initialized from the fixture with a fake transport
+    fn fake() -> (FakeTransport, TuiState) {
+        fake_with(Config::default())
+    }
+
+    fn fake_with(config: Config) -> (FakeTransport, TuiState) {
+        let transport = FakeTransport::default();
+        let mut state = TuiState::with_transport(Box::new(transport.clone()), &config);
+        transport.push(Event::Init(serde_json::from_str(INIT).unwrap()));
+        state.update();
+        // Forget the open and names of the initial channel
+        transport.take_emitted();
+        (transport, state)
+    }
+
+    fn emitted_names(transport: &FakeTransport) -> Vec<String> {
+        transport
+            .take_emitted()
+            .into_iter()
+            .map(|(event, _)| event)
+            .collect()
+    }
+
+    fn connect(session: Session) -> (MockServer, TuiState) {
+        let server = MockServer::start(session);
+        let mut state = TuiState::new(server.login(), &Config::default()).unwrap();
+        update_until(&mut state, |state| !state.networks.is_empty());
+        (server, state)
+    }
+
+    /// Process events until the condition holds
+    fn update_until(state: &mut TuiState, cond: impl Fn(&TuiState) -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !cond(state) {
+            assert!(Instant::now() < deadline, "Timed out waiting for state");
+            state.update();
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn message(id: u32, nick: &str, text: &str) -> Value {
+        json!({
+            "from": {"mode": "", "nick": nick},
+            "gecos": null,
+            "hostmask": null,
+            "id": id,
+            "self": false,
+            "text": text,
+            "time": "2024-04-20T11:00:00.000Z",
+            "type": "message",
+        })
+    }
+
+    #[test]
+    fn init_opens_active_channel() {
+        let (server, state) = connect(Session::parse(BASIC_SESSION));
+
+        assert_eq!(state.active, 2);
+        assert_eq!((state.network_idx, state.channel_idx), (0, 1));
+        assert_eq!(state.channel(2).unwrap().messages.len(), 2);
+
+        let auth = server.wait_for("auth:perform", 1);
+        assert_eq!(auth[0], json!({"user": "duck", "password": "duck"}));
+        assert_eq!(server.wait_for("open", 1)[0], json!(2));
+        assert_eq!(server.wait_for("names", 1)[0], json!({"target": 2}));
+    }
+
+    #[test]
+    fn unreachable_lounge_is_an_error() {
+        // Nothing listens on a port that was just freed
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let login = Login {
+            url: format!("http://127.0.0.1:{port}"),
+            ..Login::default()
+        };
+        assert!(TuiState::new(login, &Config::default()).is_err());
+    }
+
+    #[test]
+    fn msg_counts_unread_in_inactive_channel() {
+        let (server, mut state) = connect(Session::parse(BASIC_SESSION));
+
+        server.send(
+            "msg",
+            json!({"chan": 3, "msg": message(20, "ferris", "you there?"), "unread": 1}),
+        );
+        update_until(&mut state, |state| {
+            state.channel(3).unwrap().messages.len() == 2
+        });
+        assert_eq!(state.channel(3).unwrap().unread, 1);
+
+        server.send(
+            "msg",
+            json!({"chan": 2, "msg": message(21, "ferris", "hey")}),
+        );
+        update_until(&mut state, |state| {
+            state.channel(2).unwrap().messages.len() == 3
+        });
+        assert_eq!(state.channel(2).unwrap().unread, 0);
+    }
+
+    #[test]
+    fn invalid_payloads_are_skipped() {
+        let (server, mut state) = connect(Session::parse(BASIC_SESSION));
+
+        server.send("msg", json!({"chan": "not a channel id"}));
+        server.send(
+            "msg",
+            json!({"chan": 2, "msg": message(21, "ferris", "still here")}),
+        );
+        update_until(&mut state, |state| {
+            state.channel(2).unwrap().messages.len() == 3
+        });
+        assert_eq!(state.channel(2).unwrap().messages[2].text, "still here");
+    }
+
+    #[test]
+    fn next_channel_loads_history() {
+        let (server, mut state) = connect(Session::parse(BASIC_SESSION));
+
+        state.next_channel();
+        assert_eq!(state.active, 3);
+        assert!(server.wait_for("open", 2).contains(&json!(3)));
+        let more = server.wait_for("more", 1);
+        assert_eq!(more[0]["target"], json!(3));
+        assert!(server
+            .received_event("names")
+            .iter()
+            .all(|n| n["target"] != 3));
+
+        server.send(
+            "more",
+            json!({"chan": 3, "messages": [message(1, "ferris", "older")]}),
+        );
+        update_until(&mut state, |state| {
+            state.channel(3).unwrap().messages.len() == 2
+        });
+        assert_eq!(state.channel(3).unwrap().messages[0].text, "older");
+    }
+
+    #[test]
+    fn part_of_active_channel_selects_neighbour() {
+        let (server, mut state) = connect(Session::parse(BASIC_SESSION));
+
+        server.send("part", json!({"chan": 2}));
+        update_until(&mut state, |state| state.channel(2).is_none());
+        assert_eq!(state.active, 3);
+        assert_eq!((state.network_idx, state.channel_idx), (0, 1));
+    }
+
+    #[test]
+    fn scripted_session_waits_for_client() {
+        let session = Session::parse(BASIC_SESSION)
+            .expect("open")
+            .send("part", json!({"chan": 3}));
+        let (_server, mut state) = connect(session);
+
+        update_until(&mut state, |state| state.channel(3).is_none());
+        assert_eq!(state.active, 2);
+    }
+
+    #[test]
+    fn join_command_jumps_to_existing_channel() {
+        let (transport, mut state) = fake();
+
+        state.handle_input("/join #offtopic", state.active());
+        assert_eq!(state.active, 3);
+        assert_eq!((state.network_idx, state.channel_idx), (0, 2));
+        assert_eq!(
+            transport.take_emitted(),
+            vec![
+                ("open".into(), json!(3)),
+                ("names".into(), json!({"target": 3})),
+                ("input".into(), json!({"text": "/mode", "target": 3})),
+            ]
+        );
+    }
+
+    #[test]
+    fn join_command_for_new_channel_goes_to_server() {
+        let (transport, mut state) = fake();
+
+        state.handle_input("/join #new", state.active());
+        assert_eq!(state.active, 2);
+        assert_eq!(
+            transport.take_emitted(),
+            vec![("input".into(), json!({"text": "/join #new", "target": 2}))]
+        );
+    }
+
+    #[test]
+    fn update_active_loads_channel_once() {
+        let (transport, mut state) = fake();
+
+        state.next_channel();
+        state.next_channel();
+        assert_eq!(state.active, 4);
+        assert_eq!(
+            emitted_names(&transport),
+            ["open", "names", "input", "open", "more"]
+        );
+
+        state.prev_channel();
+        state.next_channel();
+        assert_eq!(emitted_names(&transport), ["open", "open"]);
+    }
+
+    #[test]
+    fn channel_navigation_crosses_networks() {
+        let (_, mut state) = fake();
+
+        for _ in 0..3 {
+            state.next_channel();
+        }
+        assert_eq!(state.active, 5);
+        assert_eq!((state.network_idx, state.channel_idx), (1, 0));
+
+        state.next_channel();
+        state.next_channel();
+        assert_eq!(state.active, 6);
+
+        state.prev_channel();
+        state.prev_channel();
+        assert_eq!(state.active, 4);
+
+        for _ in 0..5 {
+            state.prev_channel();
+        }
+        assert_eq!(state.active, 1);
+    }
+
+    #[test]
+    fn on_join_inserts_channel_and_focuses_it() {
+        let (transport, mut state) = fake();
+
+        let chan = json!({
+            "id": 7, "name": "#new", "type": "channel", "firstUnread": 0, "highlight": 0,
+            "key": "", "muted": false, "state": 1, "topic": "", "totalMessages": 0,
+            "unread": 0, "messages": [], "users": [],
+        });
+        let join = json!({"network": "net-libera", "index": 2, "chan": chan});
+        transport.push(Event::Join(serde_json::from_value(join).unwrap()));
+        state.update();
+
+        let names: Vec<&str> = state.networks[0]
+            .channels
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(names, ["Libera", "#rust", "#new", "#offtopic", "ferris"]);
+        assert_eq!(state.active, 7);
+        assert_eq!((state.network_idx, state.channel_idx), (0, 2));
+    }
+
+    #[test]
+    fn on_more_prepends_history() {
+        let (transport, mut state) = fake();
+
+        let more = json!({"chan": 2, "messages": [message(5, "crab", "first"), message(6, "crab", "second")]});
+        transport.push(Event::More(serde_json::from_value(more).unwrap()));
+        state.update();
+
+        let channel = state.channel(2).unwrap();
+        assert_eq!(channel.messages.len(), 7);
+        assert_eq!(channel.messages[0].text, "first");
+        assert_eq!(channel.messages[1].text, "second");
+        assert_eq!(channel.messages[2].id, 10);
+    }
+
+    #[test]
+    fn moving_channels_syncs_order() {
+        let (transport, mut state) = fake();
+
+        state.move_channel_up();
+        assert_eq!(state.networks[0].channels[1].id, 2);
+        assert!(transport.take_emitted().is_empty());
+
+        state.move_channel_down();
+        assert_eq!(state.networks[0].channels[2].id, 2);
+        assert_eq!(state.channel_idx, 2);
+        assert_eq!(
+            transport.take_emitted(),
+            vec![(
+                "sort".into(),
+                json!({"type": "channels", "target": "net-libera", "order": [1, 3, 2, 4]})
+            )]
+        );
+    }
+
+    #[test]
+    fn update_drains_all_pending_events() {
+        let (transport, mut state) = fake();
+        let (tx, rx) = mpsc::channel();
+        state.set_waker(Box::new(move || {
+            let _ = tx.send(());
+        }));
+
+        for id in 30..33 {
+            let msg = json!({"chan": 2, "msg": message(id, "crab", "spam")});
+            transport.push(Event::Msg(serde_json::from_value(msg).unwrap()));
+        }
+        assert_eq!(rx.try_iter().count(), 3);
+
+        assert!(state.update());
+        assert_eq!(state.channel(2).unwrap().messages.len(), 8);
+        assert!(!state.update());
+    }
+
+    #[test]
+    fn init_without_networks_has_no_active_channel() {
+        let transport = FakeTransport::default();
+        let mut state = TuiState::with_transport(Box::new(transport.clone()), &Config::default());
+        let init = json!({"active": -1, "networks": [], "token": ""});
+        transport.push(Event::Init(serde_json::from_value(init).unwrap()));
+        state.update();
+
+        assert_eq!(state.active, 0);
+        // Nothing is opened, only the settings are requested
+        assert_eq!(emitted_names(&transport), ["setting:get"]);
+
+        state.next_channel();
+        state.prev_channel();
+        state.move_channel_up();
+        state.move_channel_down();
+        state.close_active();
+        state.toggle_mute();
+        state.handle_input("/join #rust", state.active());
+        assert_eq!(state.active, 0);
+    }
+
+    #[test]
+    fn navigation_skips_networks_without_channels() {
+        let (_, mut state) = fake();
+        let empty = json!({"uuid": "net-empty", "name": "Empty", "nick": "duck", "channels": []});
+        state
+            .networks
+            .insert(1, serde_json::from_value(empty).unwrap());
+        state.reindex();
+        assert_eq!(state.channel(5).map(|c| c.id), Some(5));
+
+        state.next_channel();
+        state.next_channel();
+        state.next_channel();
+        assert_eq!(state.active, 5);
+        assert_eq!((state.network_idx, state.channel_idx), (2, 0));
+
+        state.prev_channel();
+        assert_eq!(state.active, 4);
+        assert_eq!((state.network_idx, state.channel_idx), (0, 3));
+    }
+
+    #[test]
+    fn quitting_last_network_clears_selection() {
+        let (transport, mut state) = fake();
+
+        transport.push(Event::Quit(
+            serde_json::from_value(json!({"network": "net-oftc"})).unwrap(),
+        ));
+        transport.push(Event::Quit(
+            serde_json::from_value(json!({"network": "net-libera"})).unwrap(),
+        ));
+        state.update();
+
+        assert!(state.networks.is_empty());
+        assert_eq!(state.active, 0);
+        assert!(state.channel(2).is_none());
+        state.next_channel();
+        state.prev_channel();
+    }
+
+    #[test]
+    fn index_follows_reordered_channels() {
+        let (transport, mut state) = fake();
+
+        state.move_channel_down();
+        transport.push(Event::SyncSort(
+            serde_json::from_value(
+                json!({"type": "channels", "target": "net-libera", "order": [1, 4, 3, 2]}),
+            )
+            .unwrap(),
+        ));
+        state.update();
+
+        for (network_idx, network) in state.networks.iter().enumerate() {
+            for (channel_idx, channel) in network.channels.iter().enumerate() {
+                assert_eq!(state.index[&channel.id], (network_idx, channel_idx));
+            }
+        }
+        assert_eq!(state.active, 2);
+        assert_eq!((state.network_idx, state.channel_idx), (0, 3));
+    }
+
+    fn push_messages(transport: &FakeTransport, chan: u32, ids: std::ops::Range<u32>) {
+        for id in ids {
+            let msg = json!({"chan": chan, "msg": message(id, "crab", "spam")});
+            transport.push(Event::Msg(serde_json::from_value(msg).unwrap()));
+        }
+    }
+
+    fn buffer(channel_messages: usize, total_messages: usize) -> Config {
+        Config {
+            buffer: BufferConfig {
+                channel_messages,
+                total_messages,
+            },
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn inactive_channels_are_trimmed_to_limit() {
+        let (transport, mut state) = fake_with(buffer(4, 100));
+        push_messages(&transport, 2, 30..40);
+        push_messages(&transport, 3, 30..40);
+        state.update();
+
+        assert_eq!(state.channel(2).unwrap().messages.len(), 15);
+        let ids: Vec<u32> = state
+            .channel(3)
+            .unwrap()
+            .messages
+            .iter()
+            .map(|m| m.id)
+            .collect();
+        assert_eq!(ids, [36, 37, 38, 39]);
+
+        // Leaving the channel trims it too
+        state.next_channel();
+        assert_eq!(state.channel(2).unwrap().messages.len(), 4);
+    }
+
+    #[test]
+    fn total_limit_trims_largest_inactive_channel() {
+        let (transport, mut state) = fake_with(buffer(100, 12));
+        push_messages(&transport, 3, 30..40);
+        push_messages(&transport, 6, 50..52);
+        state.update();
+
+        let total: usize = state
+            .networks
+            .iter()
+            .flat_map(|n| &n.channels)
+            .map(|c| c.messages.len())
+            .sum();
+        assert!(total <= 12);
+        assert_eq!(state.channel(2).unwrap().messages.len(), 5);
+        assert!(state.channel(3).unwrap().messages.len() < 10);
+        assert_eq!(state.channel(6).unwrap().messages.len(), 2);
+    }
+
+    #[test]
+    fn scrolling_into_trimmed_history_fetches_it_again() {
+        let (transport, mut state) = fake_with(buffer(4, 100));
+        state.next_channel();
+        state.prev_channel();
+        push_messages(&transport, 3, 30..40);
+        state.update();
+        state.next_channel();
+        transport.take_emitted();
+
+        state.scroll_up(1);
+        assert_eq!(state.scroll(), 1);
+        assert_eq!(
+            transport.take_emitted(),
+            vec![(
+                "more".into(),
+                json!({"target": 3, "lastId": 36, "condensed": false})
+            )]
+        );
+
+        // Only one request at a time
+        state.scroll_up(10);
+        assert_eq!(state.scroll(), 3);
+        assert!(transport.take_emitted().is_empty());
+
+        let more =
+            json!({"chan": 3, "messages": [message(35, "crab", "spam")], "totalMessages": 13});
+        transport.push(Event::More(serde_json::from_value(more).unwrap()));
+        state.update();
+        assert_eq!(state.channel(3).unwrap().messages[0].id, 35);
+        assert!(!state.channel(3).unwrap().fetching);
+
+        // Nothing more on the server
+        state.scroll_up(1);
+        transport.take_emitted();
+        let more = json!({"chan": 3, "messages": [], "totalMessages": 13});
+        transport.push(Event::More(serde_json::from_value(more).unwrap()));
+        state.update();
+        state.scroll_up(1);
+        assert!(transport.take_emitted().is_empty());
+    }
+
+    fn query_join(id: u32, nick: &str, should_open: bool) -> Event {
+        let chan = json!({
+            "id": id, "name": nick, "type": "query", "firstUnread": 0, "highlight": 0,
+            "key": "", "muted": false, "state": 1, "topic": "", "totalMessages": 0,
+            "unread": 0, "messages": [], "users": [],
+        });
+        let join =
+            json!({"network": "net-libera", "index": 5, "chan": chan, "shouldOpen": should_open});
+        Event::Join(serde_json::from_value(join).unwrap())
+    }
+
+    #[test]
+    fn query_command_focuses_existing_query() {
+        let (transport, mut state) = fake();
+
+        state.handle_input("/query Ferris", state.active());
+        assert_eq!(state.active, 4);
+        assert_eq!(emitted_names(&transport), ["open", "more"]);
+    }
+
+    #[test]
+    fn msg_command_focuses_query_created_by_server() {
+        let (transport, mut state) = fake();
+
+        state.handle_input("/msg crab hello there", 2);
+        assert_eq!(
+            transport.take_emitted(),
+            vec![(
+                "input".into(),
+                json!({"text": "/msg crab hello there", "target": 2})
+            )]
+        );
+
+        // Queries started by others don't steal the focus
+        transport.push(query_join(7, "bors", false));
+        state.update();
+        assert_eq!(state.active, 2);
+
+        transport.push(query_join(8, "crab", false));
+        state.update();
+        assert_eq!(state.active, 8);
+        assert_eq!(state.pending_query, None);
+    }
+
+    #[test]
+    fn msg_command_to_channel_opens_nothing() {
+        let (transport, mut state) = fake();
+
+        state.handle_input("/msg #offtopic hi", 2);
+        assert_eq!(state.active, 2);
+        assert_eq!(state.pending_query, None);
+        assert_eq!(emitted_names(&transport), ["input"]);
+    }
+
+    #[test]
+    fn private_messages_are_highlights() {
+        let (transport, mut state) = fake();
+
+        let msg = json!({"chan": 4, "msg": message(30, "ferris", "ping"), "unread": 3});
+        transport.push(Event::Msg(serde_json::from_value(msg).unwrap()));
+        state.update();
+
+        assert_eq!(state.channel(4).unwrap().highlight, 2);
+        assert!(state.take_notification());
+    }
+
+    #[test]
+    fn whois_reply_opens_popup() {
+        let (transport, mut state) = fake();
+
+        state.request_whois("crab");
+        assert_eq!(
+            transport.take_emitted(),
+            vec![("input".into(), json!({"text": "/whois crab", "target": 2}))]
+        );
+
+        let mut reply = message(40, "crab", "");
+        reply["type"] = json!("whois");
+        reply["whois"] = json!({"nick": "crab", "ident": "~crab", "hostname": "reef"});
+        let msg = json!({"chan": 2, "msg": reply});
+        transport.push(Event::Msg(serde_json::from_value(msg).unwrap()));
+        state.update();
+
+        assert_eq!(state.whois().unwrap().hostname.as_deref(), Some("reef"));
+        assert_eq!(state.pending_whois, None);
+        state.close_whois();
+        assert!(state.whois().is_none());
+    }
+
+    #[test]
+    fn blank_input_is_ignored() {
+        let (transport, mut state) = fake();
+
+        state.handle_input("   ", 2);
+        state.handle_input("", 2);
+        assert!(transport.take_emitted().is_empty());
+    }
+
+    #[test]
+    fn unknown_commands_go_to_server() {
+        let (transport, mut state) = fake();
+
+        state.handle_input("/away lunch", 2);
+        assert_eq!(
+            transport.take_emitted(),
+            vec![("input".into(), json!({"text": "/away lunch", "target": 2}))]
+        );
+    }
+
+    #[test]
+    fn help_lists_client_and_server_commands() {
+        let (transport, mut state) = fake();
+        transport.push(Event::Commands(vec!["/away".into(), "/join".into()]));
+        state.update();
+
+        state.handle_input("/help", 2);
+        let (title, lines) = state.take_view().unwrap();
+        assert_eq!(title, "Help");
+        assert!(lines.iter().any(|l| l.starts_with("  /join <channel>")));
+        assert_eq!(lines.last().unwrap(), "  /away");
+        assert!(transport.take_emitted().is_empty());
+
+        state.handle_input("/help query", 2);
+        let (_, lines) = state.take_view().unwrap();
+        assert_eq!(lines, ["/query <nick>", "  Open a private conversation"]);
+    }
+
+    #[test]
+    fn alias_expands_before_running() {
+        let (transport, mut state) = fake();
+
+        state.handle_input("/alias j /join", 2);
+        state.handle_input("/j #offtopic", 2);
+        assert_eq!(state.active, 3);
+
+        state.handle_input("/alias op /msg ChanServ op", 3);
+        transport.take_emitted();
+        state.handle_input("/op #rust", 3);
+        assert_eq!(
+            transport.take_emitted(),
+            vec![(
+                "input".into(),
+                json!({"text": "/msg ChanServ op #rust", "target": 3})
+            )]
+        );
+    }
+
+    #[test]
+    fn completion_depends_on_command() {
+        let (transport, mut state) = fake();
+        transport.push(Event::Commands(vec!["/away".into()]));
+        state.update();
+
+        assert_eq!(state.complete("/aw").as_deref(), Some("/away "));
+        assert_eq!(state.complete("/he").as_deref(), Some("/help "));
+        assert_eq!(
+            state.complete("/join #off").as_deref(),
+            Some("/join #offtopic ")
+        );
+        assert_eq!(
+            state.complete("/query FER").as_deref(),
+            Some("/query ferris ")
+        );
+        assert_eq!(state.complete("thanks cr").as_deref(), Some("thanks crab "));
+        assert_eq!(
+            state.complete("/set buffer.t").as_deref(),
+            Some("/set buffer.total_messages ")
+        );
+        assert_eq!(state.complete("/clear x"), None);
+    }
+
+    #[test]
+    fn set_changes_local_settings() {
+        let (_, mut state) = fake();
+
+        state.handle_input("/set buffer.channel_messages 2", 2);
+        assert!(state.take_view().is_none());
+        assert_eq!(state.buffer.channel_messages, 2);
+        // The active channel is never trimmed
+        assert_eq!(state.channel(2).unwrap().messages.len(), 5);
+
+        state.handle_input("/set buffer.channel_messages lots", 2);
+        let (_, lines) = state.take_view().unwrap();
+        assert_eq!(lines, ["buffer.channel_messages has to be a number"]);
+    }
+
+    #[test]
+    fn clear_and_quit_are_local() {
+        let (transport, mut state) = fake();
+
+        state.handle_input("/clear", 2);
+        assert!(state.channel(2).unwrap().messages.is_empty());
+        state.handle_input("/quit", 2);
+        assert!(state.quit_requested());
+        assert!(transport.take_emitted().is_empty());
+    }
+
+    #[test]
+    fn invalid_alias_delays_are_rejected() {
+        let config: Config = toml::from_str(
+            r#"
+            [aliases]
+            later = ["/delay soon", "hi"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate().unwrap_err(),
+            "alias later: invalid delay \"soon\", expected milliseconds"
+        );
+
+        // Aliases that skipped the check fail when they run
+        let (transport, mut state) = fake_with(config);
+        state.handle_input("/later", 2);
+        let (_, lines) = state.take_view().unwrap();
+        assert_eq!(
+            lines,
+            ["/later: invalid delay \"soon\", expected milliseconds"]
+        );
+
+        state.handle_input("/alias wait /delay 1s", 2);
+        assert!(state.take_view().is_some());
+        state.handle_input("/wait", 2);
+        assert_eq!(
+            transport.take_emitted(),
+            vec![("input".into(), json!({"text": "/wait", "target": 2}))]
+        );
+    }
+
+    #[test]
+    fn config_aliases_run_lines_in_order() {
+        let config: Config = toml::from_str(
+            r#"
+            [aliases]
+            deploy = ["/msg ChanServ op $1 $nick", "/delay 0", "/mode $1 +t"]
+            hi = "hello $chan"
+            "#,
+        )
+        .unwrap();
+        let (transport, mut state) = fake_with(config);
+
+        state.handle_input("/hi", 2);
+        state.handle_input("/deploy #deploy", 2);
+        assert_eq!(
+            transport.take_emitted(),
+            vec![
+                ("input".into(), json!({"text": "hello #rust", "target": 2})),
+                (
+                    "input".into(),
+                    json!({"text": "/msg ChanServ op #deploy duck", "target": 2})
+                ),
+            ]
+        );
+
+        assert!(state.next_scheduled().is_some());
+        assert!(state.run_scheduled());
+        assert_eq!(
+            transport.take_emitted(),
+            vec![(
+                "input".into(),
+                json!({"text": "/mode #deploy +t", "target": 2})
+            )]
+        );
+        assert!(state.next_scheduled().is_none());
+
+        assert_eq!(state.complete("/dep").as_deref(), Some("/deploy "));
+        state.handle_input("/help deploy", 2);
+        let (_, lines) = state.take_view().unwrap();
+        assert_eq!(lines[0], "/deploy is an alias for");
+    }
+
+    #[test]
+    fn topic_updates_and_edits() {
+        let (transport, mut state) = fake();
+        transport.push(Event::Topic(Topic {
+            chan: 2,
+            topic: "Rust 2.0 \x02released".into(),
+        }));
+        state.update();
+        assert_eq!(state.channel(2).unwrap().topic, "Rust 2.0 \x02released");
+
+        state.handle_input("/topic", 2);
+        assert_eq!(
+            state.take_prefill().as_deref(),
+            Some("/topic Rust 2.0 \x02released")
+        );
+        assert!(transport.take_emitted().is_empty());
+
+        state.handle_input("/topic Rust 2.1", 2);
+        assert!(state.take_prefill().is_none());
+        assert_eq!(
+            transport.take_emitted(),
+            vec![(
+                "input".into(),
+                json!({"text": "/topic Rust 2.1", "target": 2})
+            )]
+        );
+    }
+
+    #[test]
+    fn channel_modes_come_from_history_or_are_requested() {
+        let transport = FakeTransport::default();
+        let mut state = TuiState::with_transport(Box::new(transport.clone()), &Config::default());
+        let mut init: Value = serde_json::from_str(INIT).unwrap();
+        let mut modes = message(15, "", "+Cnt");
+        modes["type"] = json!("mode_channel");
+        init["networks"][0]["channels"][1]["messages"]
+            .as_array_mut()
+            .unwrap()
+            .push(modes);
+        transport.push(Event::Init(serde_json::from_value(init).unwrap()));
+        state.update();
+
+        // Active #rust had them in its history, #offtopic asks once opened
+        assert_eq!(state.channel(2).unwrap().modes, "+Cnt");
+        assert_eq!(emitted_names(&transport), ["setting:get", "open", "names"]);
+        state.next_channel();
+        assert_eq!(
+            transport.take_emitted().last().unwrap(),
+            &("input".into(), json!({"text": "/mode", "target": 3}))
+        );
+    }
+
+    #[test]
+    fn connection_state_and_channel_modes() {
+        let transport = FakeTransport::default();
+        let mut state = TuiState::with_transport(Box::new(transport.clone()), &Config::default());
+        assert_eq!(state.connection(), Connection::Connecting);
+
+        transport.push(Event::Connected);
+        transport.push(Event::Init(serde_json::from_str(INIT).unwrap()));
+        let mut modes = message(60, "", "+nt");
+        modes["type"] = json!("mode_channel");
+        let msg = json!({"chan": 2, "msg": modes});
+        transport.push(Event::Msg(serde_json::from_value(msg).unwrap()));
+        state.update();
+        assert_eq!(state.connection(), Connection::Connected);
+        assert_eq!(state.channel(2).unwrap().modes, "+nt");
+
+        transport.push(Event::Disconnected);
+        state.update();
+        assert_eq!(state.connection(), Connection::Disconnected);
+    }
+
+    #[test]
+    fn failed_emits_mark_the_connection_lost() {
+        let (transport, mut state) = fake();
+        transport.push(Event::Connected);
+        state.update();
+
+        transport.close();
+        state.handle_input("hello", 2);
+        state.update();
+        assert_eq!(state.connection(), Connection::Disconnected);
+    }
+
+    #[test]
+    fn sessions_screen_signs_out() {
+        let (transport, mut state) = fake();
+        let sessions = json!([
+            {"current": true, "active": true, "lastUse": 1, "ip": "::1", "agent": "tui", "token": "mine"},
+            {"current": false, "active": false, "lastUse": 1, "ip": "10.0.0.2", "agent": "Firefox", "token": "laptop"},
+            {"current": false, "active": true, "lastUse": 1, "ip": "10.0.0.3", "agent": "Chrome", "token": "phone"},
+        ]);
+
+        // Lists are ignored unless the screen is open
+        transport.push(Event::Sessions(
+            serde_json::from_value(sessions.clone()).unwrap(),
+        ));
+        state.update();
+        assert!(state.sessions().is_none());
+
+        state.handle_input("/sessions", 2);
+        assert_eq!(emitted_names(&transport), ["sessions:get"]);
+        transport.push(Event::Sessions(serde_json::from_value(sessions).unwrap()));
+        state.update();
+        assert_eq!(state.sessions().unwrap().len(), 3);
+
+        state.sign_out_others();
+        assert_eq!(
+            transport.take_emitted(),
+            vec![
+                ("sign-out".into(), json!("laptop")),
+                ("sign-out".into(), json!("phone")),
+            ]
+        );
+        assert!(!state.quit_requested());
+
+        state.sign_out(&["mine".into()]);
+        assert_eq!(
+            transport.take_emitted(),
+            vec![("sign-out".into(), json!(null))]
+        );
+        assert!(state.quit_requested());
+    }
+
+    #[test]
+    fn signing_out_removes_the_token_file() {
+        let (transport, mut state) = fake();
+        let path = std::env::temp_dir().join(format!("thelounge-tui-{}.json", std::process::id()));
+        state.token_file = Some(TokenFile::new(path.clone(), &Login::default()));
+
+        transport.push(Event::Init(serde_json::from_str(INIT).unwrap()));
+        state.update();
+        let file = TokenFile::new(path.clone(), &Login::default());
+        assert_eq!(file.load().as_deref(), Some("fixture-token"));
+
+        state.handle_input("/sessions", 2);
+        let sessions = json!([
+            {"current": true, "active": true, "lastUse": 1, "ip": "::1", "agent": "tui", "token": "fixture-token"},
+        ]);
+        transport.push(Event::Sessions(serde_json::from_value(sessions).unwrap()));
+        state.update();
+
+        state.sign_out(&["fixture-token".into()]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn upload_checks_the_server_configuration() {
+        let (transport, mut state) = fake();
+
+        state.handle_input("/upload Cargo.toml", 2);
+        let (_, lines) = state.take_view().unwrap();
+        assert_eq!(lines, ["Uploads are disabled on the server"]);
+
+        transport.push(Event::Configuration(
+            serde_json::from_value(json!({"fileUpload": true, "fileUploadMaxFileSize": 10}))
+                .unwrap(),
+        ));
+        state.update();
+        state.handle_input("/upload Cargo.toml", 2);
+        let (_, lines) = state.take_view().unwrap();
+        assert!(lines[0].ends_with("the server allows at most 10 bytes"));
+
+        state.handle_input("/upload no-such-file", 2);
+        assert!(state.take_view().is_some());
+        assert!(transport.take_emitted().is_empty());
+    }
+
+    #[test]
+    fn upload_posts_the_file() {
+        let config = json!({"fileUpload": true, "fileUploadMaxFileSize": -1});
+        let session = Session::parse(BASIC_SESSION)
+            .send("configuration", config)
+            .expect("upload:auth")
+            .send("upload:auth", json!("t0k3n"));
+        let (server, mut state) = connect(session);
+        update_until(&mut state, |state| state.configuration.file_upload);
+
+        state.handle_input("/upload Cargo.toml", 2);
+        assert!(state.take_view().is_none());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !state.poll_upload() {
+            assert!(Instant::now() < deadline, "Upload never finished");
+            state.update();
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let url = state.take_uploaded().unwrap();
+        assert_eq!(url, format!("{}/uploads/t0k3n/file", server.login().url));
+        let (token, body) = server.uploads().remove(0);
+        assert_eq!(token, "t0k3n");
+        let body = String::from_utf8(body).unwrap();
+        assert!(body.contains("filename=\"Cargo.toml\""));
+        assert!(body.contains("name = \"thelounge-cli\""));
+    }
+
+    #[test]
+    fn upload_completes_paths() {
+        let (_, state) = fake();
+
+        assert_eq!(
+            state.complete("/upload src/tu").as_deref(),
+            Some("/upload src/tui/")
+        );
+        assert_eq!(
+            state.complete("/upload src/tui/mo").as_deref(),
+            Some("/upload src/tui/mod.rs ")
+        );
+    }
+
+    #[test]
+    fn synced_settings_are_loaded_and_written_back() {
+        let session = Session::parse(BASIC_SESSION).expect("setting:get").send(
+            "setting:all",
+            json!({"nickPostfix": ":", "highlights": "rust"}),
+        );
+        let (server, mut state) = connect(session);
+        server.wait_for("setting:get", 1);
+        update_until(&mut state, |state| {
+            !state.synced.display("highlights").is_empty()
+        });
+
+        state.handle_input("/set", 2);
+        let (_, lines) = state.take_view().unwrap();
+        assert!(lines.contains(&"Synced with the Lounge".to_string()));
+        assert!(lines.contains(&"  highlights = rust".to_string()));
+
+        state.handle_input("/set awayMessage gone fishing", 2);
+        let value = json!({"name": "awayMessage", "value": "gone fishing"});
+        assert_eq!(server.wait_for("setting:set", 1), [value]);
+        assert_eq!(state.synced.display("awayMessage"), "gone fishing");
+    }
+
+    #[test]
+    fn synced_settings_change_completion() {
+        let (transport, mut state) = fake();
+        transport.push(Event::SettingAll(
+            serde_json::from_value(json!({"nickPostfix": ", "})).unwrap(),
+        ));
+        state.update();
+        assert_eq!(state.complete("fe").as_deref(), Some("ferris, "));
+        assert_eq!(state.complete("hi fe").as_deref(), Some("hi ferris "));
+
+        transport.push(Event::SettingNew(SettingNew {
+            name: "autocomplete".into(),
+            value: json!(false),
+        }));
+        state.update();
+        assert_eq!(state.complete("fe"), None);
+        // Commands still complete their arguments
+        assert_eq!(
+            state.complete("/query fe").as_deref(),
+            Some("/query ferris ")
+        );
+
+        state.handle_input("/set statusMessages sometimes", 2);
+        let (_, lines) = state.take_view().unwrap();
+        assert_eq!(
+            lines,
+            ["statusMessages has to be one of shown, condensed, hidden"]
+        );
+        assert!(transport.take_emitted().is_empty());
+    }
+
+    #[test]
+    fn status_messages_per_channel() {
+        let (transport, mut state) = fake();
+        assert_eq!(state.status_messages(2), StatusMessages::Condensed);
+
+        transport.push(Event::SettingNew(SettingNew {
+            name: "statusMessages".into(),
+            value: json!("hidden"),
+        }));
+        state.update();
+        state.handle_input("/statusmessages shown", 2);
+        assert_eq!(state.status_messages(2), StatusMessages::Shown);
+        assert_eq!(state.status_messages(3), StatusMessages::Hidden);
+
+        state.handle_input("/statusmessages", 2);
+        let (_, lines) = state.take_view().unwrap();
+        assert_eq!(lines, ["Status messages in #rust are shown"]);
+
+        state.handle_input("/statusmessages default", 2);
+        assert_eq!(state.status_messages(2), StatusMessages::Hidden);
+        assert!(transport.take_emitted().is_empty());
+    }
+
+    #[test]
+    fn status_messages_are_saved_in_the_config() {
+        let path = env::temp_dir().join(format!("thelounge-tui-config-{}.toml", process::id()));
+        fs::write(&path, "# Logs\n[log]\nenabled = false\n").unwrap();
+        let (_, mut state) = fake();
+        state.config_path = Some(path.clone());
+
+        state.handle_input("/statusmessages hidden", 2);
+        state.next_channel();
+        state.handle_input("/statusmessages shown", 3);
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# Logs\n[log]\nenabled = false\n"));
+
+        // Channel ids change between sessions, names don't
+        let config: Config = toml::from_str(&content).unwrap();
+        let (_, state) = fake_with(config);
+        assert_eq!(state.status_messages(2), StatusMessages::Hidden);
+        assert_eq!(state.status_messages(3), StatusMessages::Shown);
+
+        let mut state = state;
+        state.config_path = Some(path.clone());
+        state.handle_input("/statusmessages default", 2);
+        let config: Config = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            config.status_messages["Libera"].keys().collect::<Vec<_>>(),
+            ["#offtopic"]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    fn mention(chan: u32, msg_id: u32, time: &str) -> Value {
+        json!({
+            "chanId": chan, "msgId": msg_id, "type": "message", "time": time, "text": "hi me",
+            "from": {"mode": "", "nick": "crab"},
+        })
+    }
+
+    #[test]
+    fn mentions_are_grouped_and_dismissed() {
+        let (transport, mut state) = fake();
+        let mentions = json!([
+            mention(6, 30, "2024-04-20T09:00:00.000Z"),
+            mention(99, 31, "2024-04-20T08:00:00.000Z"),
+            mention(2, 12, "2024-04-20T11:00:00.000Z"),
+            mention(2, 11, "2024-04-20T10:00:00.000Z"),
+        ]);
+
+        state.handle_input("/mentions", 2);
+        assert_eq!(emitted_names(&transport), ["mentions:get"]);
+        transport.push(Event::Mentions(serde_json::from_value(mentions).unwrap()));
+        state.update();
+        let ids: Vec<u32> = state.mentions().unwrap().iter().map(|m| m.msg_id).collect();
+        assert_eq!(ids, [11, 12, 30, 31]);
+
+        state.dismiss_mention(12);
+        assert_eq!(state.mentions().unwrap().len(), 3);
+        state.dismiss_all_mentions();
+        assert!(state.mentions().unwrap().is_empty());
+        assert_eq!(
+            transport.take_emitted(),
+            [
+                ("mentions:dismiss".into(), json!(12)),
+                ("mentions:dismiss_all".into(), json!(null))
+            ]
+        );
+    }
+
+    #[test]
+    fn jumping_to_mention_loads_history() {
+        let (transport, mut state) = fake();
+        state.open_mentions();
+        state.jump_to_mention(4, 3);
+        assert!(state.mentions().is_none());
+        assert_eq!(state.active, 4);
+        // Opening the query fetches its history first
+        assert_eq!(emitted_names(&transport), ["mentions:get", "open", "more"]);
+
+        let more = json!({"chan": 4, "totalMessages": 40, "messages": (5..9).map(|id| message(id, "ferris", "")).collect::<Vec<_>>()});
+        transport.push(Event::More(serde_json::from_value(more).unwrap()));
+        state.update();
+        assert_eq!(
+            transport.take_emitted(),
+            [(
+                "more".into(),
+                json!({"target": 4, "lastId": 5, "condensed": false})
+            )]
+        );
+
+        let more = json!({"chan": 4, "totalMessages": 40, "messages": (1..5).map(|id| message(id, "ferris", "")).collect::<Vec<_>>()});
+        transport.push(Event::More(serde_json::from_value(more).unwrap()));
+        state.update();
+        // Mention is the third of nine messages, with five newer ones below
+        assert_eq!(state.scroll, 1);
+        assert!(transport.take_emitted().is_empty());
+    }
+
+    #[test]
+    fn unread_marker_moves_once_channel_is_left() {
+        let (transport, mut state) = fake();
+        let first_unread = |state: &TuiState, id| state.channel(id).unwrap().first_unread;
+        state.channel_mut(2).unwrap().first_unread = 14;
+
+        // Nothing was unread, so arriving messages are read right away
+        let msg = json!({"chan": 2, "msg": message(15, "crab", "hi")});
+        transport.push(Event::Msg(serde_json::from_value(msg).unwrap()));
+        state.update();
+        assert_eq!(first_unread(&state, 2), 15);
+
+        // The marker stays while the channel is open
+        state.channel_mut(2).unwrap().first_unread = 12;
+        let msg = json!({"chan": 2, "msg": message(16, "crab", "hi")});
+        transport.push(Event::Msg(serde_json::from_value(msg).unwrap()));
+        state.update();
+        assert_eq!(first_unread(&state, 2), 12);
+
+        state.next_channel();
+        assert_eq!(first_unread(&state, 2), 16);
+        assert_eq!(first_unread(&state, 3), 0);
     }
 }
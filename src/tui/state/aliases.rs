@@ -0,0 +1,178 @@
+//! Expansion of aliases into the lines they run
+
+use std::time::Duration;
+
+use crate::config::Alias;
+
+/// Values substituted into alias lines besides the arguments
+pub struct Vars<'a> {
+    pub nick: &'a str,
+    pub chan: &'a str,
+    pub network: &'a str,
+}
+
+/// Single step of an expanded alias
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Input to run like it was typed
+    Line(String),
+    /// Wait before running the next line
+    Delay(Duration),
+}
+
+/// Expand the lines of an alias with the given arguments. Arguments of an
+/// alias with a single line and no positional parameters are appended to it.
+/// Fails on a `/delay` without a valid number of milliseconds.
+pub fn expand(lines: &[String], args: &[&str], vars: &Vars) -> Result<Vec<Step>, String> {
+    let positional = lines.iter().any(|line| has_positional(line));
+
+    lines
+        .iter()
+        .map(|line| {
+            if let Some(delay) = Alias::delay(line) {
+                return delay.map(Step::Delay);
+            }
+
+            let mut line = substitute(line, args, vars);
+            if !positional && lines.len() == 1 && !args.is_empty() {
+                line.push(' ');
+                line.push_str(&args.join(" "));
+            }
+            Ok(Step::Line(line))
+        })
+        .collect()
+}
+
+fn has_positional(line: &str) -> bool {
+    line.as_bytes()
+        .windows(2)
+        .any(|w| w[0] == b'$' && w[1].is_ascii_digit())
+}
+
+fn substitute(line: &str, args: &[&str], vars: &Vars) -> String {
+    let mut out = String::new();
+    let mut rest = line;
+
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits > 0 {
+            let n: usize = rest[..digits].parse().unwrap_or(0);
+            rest = &rest[digits..];
+            // Arguments are numbered from 1
+            let from = n.saturating_sub(1);
+            if let Some(after) = rest.strip_prefix('-') {
+                rest = after;
+                out.push_str(&args.get(from..).unwrap_or_default().join(" "));
+            } else {
+                out.push_str(args.get(from).filter(|_| n > 0).unwrap_or(&""));
+            }
+            continue;
+        }
+
+        let name_len = rest.len()
+            - rest
+                .trim_start_matches(|c: char| c.is_ascii_alphabetic())
+                .len();
+        match &rest[..name_len] {
+            "nick" => out.push_str(vars.nick),
+            "chan" => out.push_str(vars.chan),
+            "network" => out.push_str(vars.network),
+            // Not a variable, keep it as it is
+            _ => {
+                out.push('$');
+                continue;
+            }
+        }
+        rest = &rest[name_len..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{expand, Step, Vars};
+
+    const VARS: Vars = Vars {
+        nick: "duck",
+        chan: "#rust",
+        network: "Libera",
+    };
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    fn line(text: &str) -> Step {
+        Step::Line(text.into())
+    }
+
+    #[test]
+    fn positional_arguments() {
+        let alias = lines(&["/msg $1 hi $2, from $3-"]);
+        assert_eq!(
+            expand(&alias, &["ferris", "crab", "the", "pond"], &VARS).unwrap(),
+            [line("/msg ferris hi crab, from the pond")]
+        );
+        assert_eq!(
+            expand(&alias, &["ferris"], &VARS).unwrap(),
+            [line("/msg ferris hi , from ")]
+        );
+    }
+
+    #[test]
+    fn variables() {
+        let alias = lines(&[
+            "/msg ChanServ op $chan $nick",
+            "$network costs $$5 $unknown",
+        ]);
+        assert_eq!(
+            expand(&alias, &[], &VARS).unwrap(),
+            [
+                line("/msg ChanServ op #rust duck"),
+                line("Libera costs $5 $unknown")
+            ]
+        );
+    }
+
+    #[test]
+    fn arguments_are_appended_without_placeholders() {
+        let alias = lines(&["/join"]);
+        assert_eq!(
+            expand(&alias, &["#rust", "key"], &VARS).unwrap(),
+            [line("/join #rust key")]
+        );
+    }
+
+    #[test]
+    fn invalid_delays_are_errors() {
+        for delay in ["/delay", "/delay soon", "/delay -5"] {
+            assert!(expand(&lines(&[delay]), &[], &VARS).is_err());
+        }
+    }
+
+    #[test]
+    fn delays_between_lines() {
+        let alias = lines(&["/msg ChanServ op #deploy", "/delay 500", "/mode #deploy +t"]);
+        assert_eq!(
+            expand(&alias, &[], &VARS).unwrap(),
+            [
+                line("/msg ChanServ op #deploy"),
+                Step::Delay(Duration::from_millis(500)),
+                line("/mode #deploy +t")
+            ]
+        );
+    }
+}
@@ -0,0 +1,646 @@
+//! Slash commands handled by the client. Everything else is sent to the
+//! Lounge server as input.
+
+use serde_json::json;
+use std::fs;
+
+use crate::{
+    config::{Alias, Config},
+    logger::format_line,
+    upload,
+};
+
+use super::{settings, StatusMessages, TuiState};
+
+/// Where the arguments of a command are completed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Completion {
+    Nothing,
+    Channel,
+    Nick,
+    Setting,
+    Command,
+    Path,
+}
+
+pub struct Command {
+    /// Name without the leading slash
+    pub name: &'static str,
+    /// Arguments shown in `/help`
+    pub args: &'static str,
+    pub help: &'static str,
+    pub completion: Completion,
+    /// Returns false if the input should still be sent to the server
+    run: fn(&mut TuiState, &[&str]) -> bool,
+}
+
+impl Command {
+    pub fn run(&self, state: &mut TuiState, args: &[&str]) -> bool {
+        (self.run)(state, args)
+    }
+
+    pub fn syntax(&self) -> String {
+        if self.args.is_empty() {
+            format!("/{}", self.name)
+        } else {
+            format!("/{} {}", self.name, self.args)
+        }
+    }
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "alias",
+        args: "[name [text]]",
+        help: "List aliases, define one for this session or remove it",
+        completion: Completion::Command,
+        run: alias,
+    },
+    Command {
+        name: "clear",
+        args: "",
+        help: "Clear the messages of the channel from the screen",
+        completion: Completion::Nothing,
+        run: clear,
+    },
+    Command {
+        name: "close",
+        args: "",
+        help: "Leave the channel or close the query",
+        completion: Completion::Nothing,
+        run: close,
+    },
+    Command {
+        name: "help",
+        args: "[command]",
+        help: "List the commands or show the help of one",
+        completion: Completion::Command,
+        run: help,
+    },
+    Command {
+        name: "join",
+        args: "<channel>",
+        help: "Jump to the channel, joining it if needed",
+        completion: Completion::Channel,
+        run: join,
+    },
+    Command {
+        name: "mentions",
+        args: "",
+        help: "List the recent highlights of all networks",
+        completion: Completion::Nothing,
+        run: mentions,
+    },
+    Command {
+        name: "msg",
+        args: "<nick> <text>",
+        help: "Send a private message and open the query",
+        completion: Completion::Nick,
+        run: msg,
+    },
+    Command {
+        name: "mute",
+        args: "[channel...]",
+        help: "Mute the channels, or the active one",
+        completion: Completion::Channel,
+        run: mute,
+    },
+    Command {
+        name: "query",
+        args: "<nick>",
+        help: "Open a private conversation",
+        completion: Completion::Nick,
+        run: query,
+    },
+    Command {
+        name: "quit",
+        args: "",
+        help: "Exit the client, the Lounge stays connected",
+        completion: Completion::Nothing,
+        run: quit,
+    },
+    Command {
+        name: "search",
+        args: "<text>",
+        help: "List the loaded messages of the channel containing the text",
+        completion: Completion::Nothing,
+        run: search,
+    },
+    Command {
+        name: "sessions",
+        args: "",
+        help: "List the logged in clients and sign them out",
+        completion: Completion::Nothing,
+        run: sessions,
+    },
+    Command {
+        name: "set",
+        args: "[setting [value]]",
+        help: "List the settings, show one or change it",
+        completion: Completion::Setting,
+        run: set,
+    },
+    Command {
+        name: "statusmessages",
+        args: "[shown|condensed|hidden|default]",
+        help: "Show how joins, parts and quits appear in the channel or change it",
+        completion: Completion::Nothing,
+        run: status_messages,
+    },
+    Command {
+        name: "topic",
+        args: "[topic]",
+        help: "Change the topic, or edit the current one",
+        completion: Completion::Nothing,
+        run: topic,
+    },
+    Command {
+        name: "unmute",
+        args: "[channel...]",
+        help: "Unmute the channels, or the active one",
+        completion: Completion::Channel,
+        run: unmute,
+    },
+    Command {
+        name: "upload",
+        args: "<path>",
+        help: "Upload a file to the Lounge and put its URL into the input",
+        completion: Completion::Path,
+        run: upload,
+    },
+    Command {
+        name: "whois",
+        args: "<nick>",
+        help: "Show information about the user in a popup",
+        completion: Completion::Nick,
+        run: whois,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|c| c.name == name)
+}
+
+/// Local settings that `/set` can change
+const SETTINGS: &[&str] = &["buffer.channel_messages", "buffer.total_messages"];
+
+fn setting(state: &TuiState, name: &str) -> Option<String> {
+    match name {
+        "buffer.channel_messages" => Some(state.buffer.channel_messages.to_string()),
+        "buffer.total_messages" => Some(state.buffer.total_messages.to_string()),
+        _ => None,
+    }
+}
+
+fn set_setting(state: &mut TuiState, name: &str, value: &str) -> Result<(), String> {
+    let number = || {
+        value
+            .parse::<usize>()
+            .map_err(|_| format!("{name} has to be a number"))
+    };
+
+    match name {
+        "buffer.channel_messages" => state.buffer.channel_messages = number()?,
+        "buffer.total_messages" => state.buffer.total_messages = number()?,
+        _ => return Err(format!("Unknown setting {name}")),
+    }
+    state.trim_buffers();
+    Ok(())
+}
+
+fn alias(state: &mut TuiState, args: &[&str]) -> bool {
+    match args {
+        [] => {
+            let lines = state
+                .aliases
+                .iter()
+                .map(|(name, lines)| format!("/{name} = {}", lines.join(" ; ")))
+                .collect();
+            state.show("Aliases", lines);
+        }
+        [name] => {
+            state.aliases.remove(name.trim_start_matches('/'));
+        }
+        [name, text @ ..] => {
+            let name = name.trim_start_matches('/');
+            let line = text.join(" ");
+            match Alias::delay(&line) {
+                Some(Err(err)) => state.show("Alias", vec![format!("/{name}: {err}")]),
+                _ => {
+                    state.aliases.insert(name.into(), vec![line]);
+                }
+            }
+        }
+    }
+    true
+}
+
+fn clear(state: &mut TuiState, _: &[&str]) -> bool {
+    let active = state.active;
+    if let Some(channel) = state.channel_mut(active) {
+        channel.messages.clear();
+        // Don't fetch the cleared messages again
+        channel.total_messages = 0;
+    }
+    state.scroll = 0;
+    true
+}
+
+fn close(state: &mut TuiState, _: &[&str]) -> bool {
+    state.close_active();
+    true
+}
+
+fn help(state: &mut TuiState, args: &[&str]) -> bool {
+    if let [name, ..] = args {
+        let name = name.trim_start_matches('/');
+        let lines = match (find(name), state.aliases.get(name)) {
+            (Some(command), _) => vec![command.syntax(), format!("  {}", command.help)],
+            (None, Some(lines)) => {
+                let mut help = vec![format!("/{name} is an alias for")];
+                help.extend(lines.iter().map(|line| format!("  {line}")));
+                help
+            }
+            (None, None) => vec![format!("/{name} is handled by the server")],
+        };
+        state.show("Help", lines);
+        return true;
+    }
+
+    let width = COMMANDS
+        .iter()
+        .map(|c| c.syntax().len())
+        .max()
+        .unwrap_or_default();
+
+    let mut lines = vec!["Client commands".to_string()];
+    lines.extend(
+        COMMANDS
+            .iter()
+            .map(|c| format!("  {:width$}  {}", c.syntax(), c.help)),
+    );
+
+    if !state.aliases.is_empty() {
+        lines.push(String::new());
+        lines.push("Aliases".into());
+        lines.extend(
+            state
+                .aliases
+                .iter()
+                .map(|(name, lines)| format!("  /{name} = {}", lines.join(" ; "))),
+        );
+    }
+
+    let server: Vec<&str> = state
+        .server_commands
+        .iter()
+        .map(String::as_str)
+        .filter(|name| find(name.trim_start_matches('/')).is_none())
+        .collect();
+    if !server.is_empty() {
+        lines.push(String::new());
+        lines.push("Server commands".into());
+        lines.extend(
+            server
+                .chunks(6)
+                .map(|names| format!("  {}", names.join(" "))),
+        );
+    }
+
+    state.show("Help", lines);
+    true
+}
+
+fn join(state: &mut TuiState, args: &[&str]) -> bool {
+    // check if we already are in the channel and jump into it
+    let name = match args.first() {
+        Some(name) => name,
+        None => return false,
+    };
+    if let Some(channel) = state
+        .networks
+        .get(state.network_idx)
+        .and_then(|n| n.channels.iter().find(|c| c.name == *name))
+    {
+        state.focus(channel.id);
+        return true;
+    }
+
+    false
+}
+
+fn mentions(state: &mut TuiState, _: &[&str]) -> bool {
+    state.open_mentions();
+    true
+}
+
+fn msg(state: &mut TuiState, args: &[&str]) -> bool {
+    open_query(state, args);
+    false
+}
+
+fn query(state: &mut TuiState, args: &[&str]) -> bool {
+    // Opening an existing query needs nothing from the server
+    open_query(state, args)
+}
+
+/// Focus the query with the nick, or remember to focus it once the server
+/// creates it. Returns true if the query already exists.
+fn open_query(state: &mut TuiState, args: &[&str]) -> bool {
+    let nick = match args.first() {
+        // Messages to channels don't open anything
+        Some(nick) if !nick.starts_with(['#', '&']) => nick,
+        _ => return false,
+    };
+
+    let query = state.networks.get(state.network_idx).and_then(|n| {
+        n.channels
+            .iter()
+            .find(|c| c.type_ == "query" && c.name.eq_ignore_ascii_case(nick))
+    });
+    match query {
+        Some(query) => {
+            state.focus(query.id);
+            true
+        }
+        None => {
+            state.pending_query = Some(nick.to_string());
+            false
+        }
+    }
+}
+
+fn mute(state: &mut TuiState, args: &[&str]) -> bool {
+    state.mute_channels(args, true);
+    true
+}
+
+fn unmute(state: &mut TuiState, args: &[&str]) -> bool {
+    state.mute_channels(args, false);
+    true
+}
+
+fn quit(state: &mut TuiState, _: &[&str]) -> bool {
+    state.quit = true;
+    true
+}
+
+fn search(state: &mut TuiState, args: &[&str]) -> bool {
+    let text = args.join(" ").to_lowercase();
+    let channel = match state.channel(state.active) {
+        Some(channel) if !text.is_empty() => channel,
+        _ => return true,
+    };
+
+    let lines = channel
+        .messages
+        .iter()
+        .filter(|m| m.text.to_lowercase().contains(&text))
+        .map(format_line)
+        .collect();
+    let title = format!("Search {} for {}", channel.name, args.join(" "));
+    state.show(&title, lines);
+    true
+}
+
+fn sessions(state: &mut TuiState, _: &[&str]) -> bool {
+    state.open_sessions();
+    true
+}
+
+fn set(state: &mut TuiState, args: &[&str]) -> bool {
+    let lines =
+        match args {
+            [] => {
+                let mut lines = vec!["Local".to_string()];
+                lines.extend(SETTINGS.iter().map(|name| {
+                    format!("  {name} = {}", setting(state, name).unwrap_or_default())
+                }));
+                lines.push(String::new());
+                lines.push("Synced with the Lounge".into());
+                for synced in settings::SYNCED {
+                    lines.push(format!(
+                        "  {} = {}",
+                        synced.name,
+                        state.synced.display(synced.name)
+                    ));
+                    lines.push(format!("      {}", synced.help));
+                }
+                lines
+            }
+            [name] => match (setting(state, name), settings::find(name)) {
+                (Some(value), _) => vec![format!("{name} = {value}")],
+                (None, Some(synced)) => vec![
+                    format!("{name} = {}", state.synced.display(name)),
+                    format!("  {}", synced.help),
+                ],
+                (None, None) => vec![format!("Unknown setting {name}")],
+            },
+            [name, value @ ..] => match settings::find(name) {
+                Some(synced) => match set_synced(state, synced, &value.join(" ")) {
+                    Ok(()) => return true,
+                    Err(err) => vec![err],
+                },
+                None => match set_setting(state, name, value[0]) {
+                    Ok(()) => return true,
+                    Err(err) => vec![err],
+                },
+            },
+        };
+    state.show("Settings", lines);
+    true
+}
+
+/// Change a synced setting here and on the server, which passes it on to
+/// the other clients
+fn set_synced(state: &mut TuiState, synced: &settings::Synced, value: &str) -> Result<(), String> {
+    let value = settings::parse(synced, value)?;
+    state.synced.set(synced.name, value.clone());
+    state.emit("setting:set", json!({"name": synced.name, "value": value}));
+    Ok(())
+}
+
+/// Override the synced `statusMessages` setting for the active channel and
+/// save it in the config
+fn status_messages(state: &mut TuiState, args: &[&str]) -> bool {
+    let active = state.active;
+    let (network, name) = match state.channel(active).zip(state.network_of(active)) {
+        Some((channel, network)) => (network.name.clone(), channel.name.clone()),
+        None => return true,
+    };
+
+    let mode = match args {
+        [] => {
+            let line = format!(
+                "Status messages in {name} are {}",
+                state.status_messages(active).name()
+            );
+            state.show("Status messages", vec![line]);
+            return true;
+        }
+        ["default"] => None,
+        [mode] => match StatusMessages::parse(mode) {
+            Some(mode) => Some(mode),
+            None => {
+                let line = "Status messages can be shown, condensed, hidden or default";
+                state.show("Status messages", vec![line.into()]);
+                return true;
+            }
+        },
+        _ => {
+            let line = "Usage: /statusmessages [shown|condensed|hidden|default]";
+            state.show("Status messages", vec![line.into()]);
+            return true;
+        }
+    };
+
+    let channels = state.status_messages.entry(network.clone()).or_default();
+    match mode {
+        Some(mode) => channels.insert(name.clone(), mode),
+        None => channels.remove(&name),
+    };
+    if let Some(path) = &state.config_path {
+        if let Err(err) = Config::save_status_messages(path, &network, &name, mode) {
+            state.show(
+                "Status messages",
+                vec![format!("Couldn't save the config: {err}")],
+            );
+        }
+    }
+    true
+}
+
+fn topic(state: &mut TuiState, args: &[&str]) -> bool {
+    if !args.is_empty() {
+        return false;
+    }
+
+    if let Some(channel) = state.channel(state.active) {
+        state.prefill = Some(format!("/topic {}", channel.topic));
+    }
+    true
+}
+
+fn upload(state: &mut TuiState, args: &[&str]) -> bool {
+    match args {
+        [] => state.show("Upload", vec!["Usage: /upload <path>".into()]),
+        _ => state.request_upload(&args.join(" ")),
+    }
+    true
+}
+
+fn whois(state: &mut TuiState, args: &[&str]) -> bool {
+    if let Some(nick) = args.first() {
+        state.pending_whois = Some(nick.to_string());
+    }
+    false
+}
+
+/// Complete the last word of the input. Command names are completed at the
+/// start of the input, the arguments depend on the command.
+pub fn complete(state: &TuiState, input: &str) -> Option<String> {
+    let (head, word) = match input.rsplit_once(' ') {
+        Some((head, word)) => (Some(head), word),
+        None => (None, input),
+    };
+
+    let first = input.split_whitespace().next().unwrap_or_default();
+    let (candidates, prefix): (Vec<String>, &str) = match head {
+        None if word.starts_with('/') => (command_names(state), "/"),
+        _ => {
+            let completion = match first.strip_prefix('/') {
+                Some(name) => find(name).map_or(Completion::Nick, |c| c.completion),
+                None if !state.synced.autocomplete() => return None,
+                None => Completion::Nick,
+            };
+            (candidates(state, completion, word), "")
+        }
+    };
+
+    let word_lower = word.trim_start_matches(prefix).to_lowercase();
+    let found = candidates
+        .iter()
+        .find(|c| c.to_lowercase().starts_with(&word_lower))?;
+
+    // Directories are completed further
+    let space = if found.ends_with('/') { "" } else { " " };
+    Some(match head {
+        Some(head) => format!("{head} {prefix}{found}{space}"),
+        // Nick the message is addressed to, the postfix is usually `:` or `,`
+        None if prefix.is_empty() => {
+            format!("{found}{} ", state.synced.nick_postfix().trim_end())
+        }
+        None => format!("{prefix}{found}{space}"),
+    })
+}
+
+/// Client, alias and server commands without the leading slash
+fn command_names(state: &TuiState) -> Vec<String> {
+    let mut names: Vec<String> = COMMANDS
+        .iter()
+        .map(|c| c.name.to_string())
+        .chain(state.aliases.keys().cloned())
+        .chain(
+            state
+                .server_commands
+                .iter()
+                .map(|c| c.trim_start_matches('/').to_string()),
+        )
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn candidates(state: &TuiState, completion: Completion, word: &str) -> Vec<String> {
+    let mut candidates: Vec<String> = match completion {
+        Completion::Nothing => Vec::new(),
+        Completion::Channel => state
+            .networks
+            .get(state.network_idx)
+            .into_iter()
+            .flat_map(|n| &n.channels)
+            .filter(|c| c.type_ == "channel")
+            .map(|c| c.name.clone())
+            .collect(),
+        Completion::Nick => state
+            .channel(state.active)
+            .into_iter()
+            .flat_map(|c| &c.users)
+            .filter_map(|u| u.nick.clone())
+            .collect(),
+        Completion::Setting => SETTINGS
+            .iter()
+            .copied()
+            .chain(settings::SYNCED.iter().map(|s| s.name))
+            .map(String::from)
+            .collect(),
+        Completion::Command => command_names(state),
+        Completion::Path => paths(word),
+    };
+    candidates.sort_by_key(|c| c.to_lowercase());
+    candidates
+}
+
+/// Entries of the directory of a partial path, with a trailing slash for
+/// directories. Hidden files only show up once their dot is typed.
+fn paths(word: &str) -> Vec<String> {
+    let (dir, name) = match word.rfind('/') {
+        Some(slash) => word.split_at(slash + 1),
+        None => ("", word),
+    };
+    let entries = match fs::read_dir(upload::expand_home(if dir.is_empty() { "." } else { dir })) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    entries
+        .flatten()
+        .map(|entry| (entry.file_name().to_string_lossy().into_owned(), entry))
+        .filter(|(file, _)| !file.starts_with('.') || name.starts_with('.'))
+        .map(|(file, entry)| {
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            format!("{dir}{file}{slash}")
+        })
+        .collect()
+}
@@ -0,0 +1,127 @@
+//! Client settings the Lounge syncs between all clients of the user. They're
+//! kept apart from the local settings in the config file.
+
+use serde_json::{Map, Value};
+
+use crate::config::StatusMessages;
+
+/// Values a synced setting takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Text,
+    Bool,
+    Choice(&'static [&'static str]),
+}
+
+pub struct Synced {
+    /// Name used by the Lounge
+    pub name: &'static str,
+    pub kind: Kind,
+    pub help: &'static str,
+}
+
+pub const SYNCED: &[Synced] = &[
+    Synced {
+        name: "autocomplete",
+        kind: Kind::Bool,
+        help: "Complete nicks in messages with tab",
+    },
+    Synced {
+        name: "awayMessage",
+        kind: Kind::Text,
+        help: "Away message set while no client is connected",
+    },
+    Synced {
+        name: "highlightExceptions",
+        kind: Kind::Text,
+        help: "Words that keep a message from highlighting, comma separated",
+    },
+    Synced {
+        name: "highlights",
+        kind: Kind::Text,
+        help: "Words besides the nick that highlight a message, comma separated",
+    },
+    Synced {
+        name: "nickPostfix",
+        kind: Kind::Text,
+        help: "Added after a nick completed at the start of a message",
+    },
+    Synced {
+        name: "statusMessages",
+        kind: Kind::Choice(&["shown", "condensed", "hidden"]),
+        help: "How joins, parts, quits and other status messages are shown",
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Synced> {
+    SYNCED.iter().find(|s| s.name == name)
+}
+
+/// Synced settings as received from the server
+#[derive(Debug, Default)]
+pub struct Settings {
+    values: Map<String, Value>,
+}
+
+impl Settings {
+    /// Replace everything with the settings from `setting:all`
+    pub fn set_all(&mut self, values: Map<String, Value>) {
+        self.values = values;
+    }
+
+    pub fn set(&mut self, name: &str, value: Value) {
+        self.values.insert(name.into(), value);
+    }
+
+    /// Value shown to the user, empty if it's not set
+    pub fn display(&self, name: &str) -> String {
+        match self.values.get(name) {
+            Some(Value::String(text)) => text.clone(),
+            Some(Value::Null) | None => String::new(),
+            Some(value) => value.to_string(),
+        }
+    }
+
+    fn text(&self, name: &str) -> &str {
+        self.values
+            .get(name)
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+    }
+
+    /// Nicks are completed in messages, on if it's not set
+    pub fn autocomplete(&self) -> bool {
+        self.values
+            .get("autocomplete")
+            .and_then(Value::as_bool)
+            .unwrap_or(true)
+    }
+
+    /// Added after a nick completed at the start of a message
+    pub fn nick_postfix(&self) -> &str {
+        self.text("nickPostfix")
+    }
+
+    /// Condensed if it's not set, like in the web client
+    pub fn status_messages(&self) -> StatusMessages {
+        StatusMessages::parse(self.text("statusMessages")).unwrap_or(StatusMessages::Condensed)
+    }
+}
+
+/// Parse the value typed for a setting into what the Lounge expects
+pub fn parse(setting: &Synced, value: &str) -> Result<Value, String> {
+    match setting.kind {
+        Kind::Text => Ok(Value::String(value.into())),
+        Kind::Bool => match value {
+            "true" | "on" | "yes" => Ok(Value::Bool(true)),
+            "false" | "off" | "no" => Ok(Value::Bool(false)),
+            _ => Err(format!("{} has to be true or false", setting.name)),
+        },
+        Kind::Choice(choices) if choices.contains(&value) => Ok(Value::String(value.into())),
+        Kind::Choice(choices) => Err(format!(
+            "{} has to be one of {}",
+            setting.name,
+            choices.join(", ")
+        )),
+    }
+}
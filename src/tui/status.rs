@@ -0,0 +1,178 @@
+//! Status bar below the chat, laid out by a format string from the config
+
+use ratatui::{
+    style::{Color, Style, Stylize},
+    text::{Line, Span},
+    widgets::Paragraph,
+};
+use std::time::SystemTime;
+
+use super::state::{Connection, TuiState};
+use crate::config::TimeZone;
+
+/// Time of day as `HH:MM` in the configured zone
+fn clock(now: SystemTime, zone: TimeZone) -> String {
+    zone.format_utc(now.into(), "%H:%M")
+}
+
+/// Spans of a single item, empty if there's nothing to show
+fn item(name: &str, state: &TuiState, clock: &str) -> Option<Vec<Span<'static>>> {
+    let network = state.active_network();
+    let channel = state.channel(state.active());
+
+    let spans = match name {
+        "clock" => vec![Span::raw(clock.to_string())],
+        "connection" => vec![match state.connection() {
+            Connection::Connecting => Span::raw("connecting").fg(Color::Yellow),
+            Connection::Connected => Span::raw("connected").fg(Color::Green),
+            Connection::Disconnected => Span::raw("disconnected").fg(Color::Red).bold(),
+            Connection::LoginFailed => Span::raw("login failed").fg(Color::Red).bold(),
+            Connection::SignedOut => Span::raw("signed out").fg(Color::Red).bold(),
+        }],
+        "network" => network.map(|n| vec![Span::raw(n.name.clone())])?,
+        "nick" => network.map(|n| vec![Span::raw(n.nick.clone()).bold()])?,
+        "channel" => channel.map(|c| vec![Span::raw(c.name.clone()).bold()])?,
+        "modes" => channel
+            .filter(|c| !c.modes.is_empty())
+            .map(|c| vec![Span::raw(c.modes.clone())])?,
+        "users" => channel
+            .filter(|c| c.type_ == "channel")
+            .map(|c| vec![Span::raw(format!("{} users", c.users.len()))])?,
+        "activity" => activity(state),
+        "upload" => state
+            .upload()
+            .map(|u| vec![Span::raw(format!("{} {}%", u.name, u.percent())).fg(Color::Cyan)])?,
+        _ => return None,
+    };
+
+    (!spans.is_empty()).then_some(spans)
+}
+
+/// Other channels with unread messages, highlighted ones stand out
+fn activity(state: &TuiState) -> Vec<Span<'static>> {
+    let channels = state
+        .networks()
+        .iter()
+        .flat_map(|n| &n.channels)
+        .filter(|c| c.id != state.active() && !c.muted && c.unread > 0);
+
+    let mut spans = Vec::new();
+    for channel in channels {
+        spans.push(Span::raw(if spans.is_empty() { "Act: " } else { "," }));
+        let name = Span::raw(format!("{}({})", channel.name, channel.unread));
+        spans.push(if channel.highlight > 0 {
+            name.fg(Color::Yellow).bold()
+        } else {
+            name
+        });
+    }
+    spans
+}
+
+/// Spans of a word of the format. None if it has placeholders and all of
+/// them are empty.
+fn word(word: &str, state: &TuiState, clock: &str) -> Option<Vec<Span<'static>>> {
+    let mut spans = Vec::new();
+    let mut placeholders = 0;
+    let mut filled = 0;
+    let mut rest = word;
+
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        if start > 0 {
+            spans.push(Span::raw(rest[..start].to_string()));
+        }
+        placeholders += 1;
+        if let Some(item) = item(&rest[start + 1..end], state, clock) {
+            filled += 1;
+            spans.extend(item);
+        }
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        spans.push(Span::raw(rest.to_string()));
+    }
+
+    (placeholders == 0 || filled > 0).then_some(spans)
+}
+
+/// Words of the format with the items filled in
+fn spans(format: &str, state: &TuiState, clock: &str) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    for words in format
+        .split_whitespace()
+        .filter_map(|w| word(w, state, clock))
+    {
+        if !spans.is_empty() {
+            spans.push(Span::raw(" "));
+        }
+        spans.extend(words);
+    }
+    spans
+}
+
+pub struct StatusWidget {}
+
+impl StatusWidget {
+    pub fn ui(
+        format: &str,
+        state: &TuiState,
+        now: SystemTime,
+        zone: TimeZone,
+    ) -> Paragraph<'static> {
+        Paragraph::new(Line::from(spans(format, state, &clock(now, zone))))
+            .style(Style::default().bg(Color::Blue))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{clock, spans};
+    use crate::{config::TimeZone, tui::state::TuiState};
+
+    fn state() -> TuiState {
+        let init = include_str!("fixtures/init.json");
+        TuiState::from_init(serde_json::from_str(init).unwrap())
+    }
+
+    fn text(format: &str) -> String {
+        let now = UNIX_EPOCH + Duration::from_secs(86400 + 13 * 3600 + 7 * 60 + 59);
+        spans(format, &state(), &clock(now, TimeZone::utc()))
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect()
+    }
+
+    #[test]
+    fn clock_is_hours_and_minutes_in_the_zone() {
+        let now = UNIX_EPOCH + Duration::from_secs(3600 * 25 + 61);
+        assert_eq!(clock(now, TimeZone::utc()), "01:01");
+        let zone = TimeZone::try_from("-02:00".to_string()).unwrap();
+        assert_eq!(clock(now, zone), "23:01");
+        assert_eq!(clock(SystemTime::UNIX_EPOCH, TimeZone::utc()), "00:00");
+    }
+
+    #[test]
+    fn items_are_filled_in() {
+        assert_eq!(
+            text("[{clock}] {nick}@{network} {channel} {users}"),
+            "[13:07] duck@Libera #rust 4 users"
+        );
+    }
+
+    #[test]
+    fn empty_items_drop_their_word() {
+        // No modes are known yet
+        assert_eq!(text("{channel} ({modes}) {unknown} end"), "#rust end");
+    }
+
+    #[test]
+    fn activity_skips_active_and_muted_channels() {
+        assert_eq!(text("{activity}"), "Act: ferris(2),#debian(1)");
+    }
+}
@@ -0,0 +1,437 @@
+//! Snapshot tests for the widgets and the layout.
+//!
+//! Rendered buffers are compared against the golden files in
+//! `src/tui/snapshots`. Run `BLESS=1 cargo test` to update them after an
+//! intentional change and review the diff before committing.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{
+    backend::TestBackend,
+    layout::{Constraint, Layout},
+    widgets::Clear,
+    Frame, Terminal,
+};
+use serde_json::{json, Value};
+use std::{
+    env, fs,
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use super::{
+    channels::ChannelsWidget,
+    chat::{ChatWidget, Rows, Runs, Visibility, WrapCache},
+    filters::Filters,
+    input::InputWidget,
+    logview::{LogView, LogWidget},
+    mentions::{MentionList, MentionsWidget},
+    sessions::{SessionList, SessionsWidget},
+    state::{StatusMessages, TuiState},
+    topic::TopicWidget,
+    ui,
+    users::{UserList, UsersWidget},
+    whois::WhoisWidget,
+    TuiApp,
+};
+use crate::{
+    config::{Config, TimeZone},
+    events::{Event, FakeTransport},
+};
+
+const INIT: &str = include_str!("fixtures/init.json");
+
+fn whois() -> Value {
+    json!({
+        "nick": "crab",
+        "ident": "~crab",
+        "hostname": "reef.example.org",
+        "real_name": "Crab Rave",
+        "server": "tungsten.libera.chat",
+        "server_info": "Umeå, SE, EU",
+        "channels": "@#rust #offtopic",
+        "idle": 3725,
+        "account": "crab",
+        "secure": true,
+    })
+}
+
+/// Terminal sizes the full layout is rendered at
+const SIZES: [(u16, u16); 3] = [(40, 12), (80, 24), (120, 40)];
+
+fn app() -> TuiApp {
+    let init = serde_json::from_str(INIT).expect("Invalid init fixture");
+    let mut app = TuiApp::with_state(TuiState::from_init(init));
+    // Fixed clock in the status bar, 2024-04-20 12:34
+    app.now = || UNIX_EPOCH + Duration::from_secs(1_713_616_440);
+    app.wrap_cache = cache();
+    app.timezone = TimeZone::utc();
+    app
+}
+
+/// Cache that shows message times in UTC, whatever the zone of the machine
+fn cache() -> WrapCache {
+    WrapCache::new(TimeZone::utc())
+}
+
+fn render(width: u16, height: u16, draw: impl FnOnce(&mut Frame)) -> String {
+    let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+    terminal.draw(draw).unwrap();
+    format!("{:?}", terminal.backend().buffer())
+}
+
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("src/tui/snapshots")
+        .join(format!("{name}.snap"));
+
+    if env::var_os("BLESS").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "Missing snapshot {}, run with BLESS=1 to create it",
+            path.display()
+        )
+    });
+    assert!(
+        expected == actual,
+        "Snapshot {name} differs, run with BLESS=1 to update it\n\
+         --- expected\n{expected}\n--- actual\n{actual}"
+    );
+}
+
+#[test]
+fn layout_chat_only() {
+    let mut app = app();
+    for (width, height) in SIZES {
+        let buffer = render(width, height, |frame| ui(frame, &mut app));
+        assert_snapshot(&format!("layout_chat_only_{width}x{height}"), &buffer);
+    }
+}
+
+#[test]
+fn layout_with_sidebars() {
+    let mut app = app();
+    app.show_channels = true;
+    app.show_users = true;
+    for (width, height) in SIZES {
+        let buffer = render(width, height, |frame| ui(frame, &mut app));
+        assert_snapshot(&format!("layout_with_sidebars_{width}x{height}"), &buffer);
+    }
+}
+
+#[test]
+fn layout_with_input() {
+    let mut app = app();
+    for c in "/join #rust".chars() {
+        app.input_buffer.add(c);
+    }
+    let buffer = render(80, 24, |frame| ui(frame, &mut app));
+    assert_snapshot("layout_with_input_80x24", &buffer);
+}
+
+#[test]
+fn channels_widget() {
+    let app = app();
+    let buffer = render(24, 14, |frame| {
+        let widget = ChannelsWidget::ui(app.state.networks(), app.state.active());
+        frame.render_widget(widget, frame.size());
+    });
+    assert_snapshot("channels_widget", &buffer);
+}
+
+#[test]
+fn chat_widget() {
+    let app = app();
+    let channel = app.state.channel(2).unwrap();
+    let buffer = render(60, 8, |frame| {
+        let widget = ChatWidget::ui(
+            channel.name.clone(),
+            Rows::new(
+                &channel.messages,
+                |_| Visibility::Shown,
+                StatusMessages::Shown,
+            ),
+            &mut Runs::default(),
+            &mut cache(),
+            frame.size(),
+        );
+        frame.render_widget(widget, frame.size());
+    });
+    assert_snapshot("chat_widget", &buffer);
+}
+
+#[test]
+fn chat_widget_unread_marker() {
+    let app = app();
+    let channel = app.state.channel(2).unwrap();
+    let buffer = render(60, 9, |frame| {
+        let rows = Rows::new(
+            &channel.messages,
+            |_| Visibility::Shown,
+            StatusMessages::Shown,
+        );
+        let widget = ChatWidget::ui(
+            channel.name.clone(),
+            rows.marker(12),
+            &mut Runs::default(),
+            &mut cache(),
+            frame.size(),
+        );
+        frame.render_widget(widget, frame.size());
+    });
+    assert_snapshot("chat_widget_unread_marker", &buffer);
+}
+
+#[test]
+fn chat_widget_wraps_long_messages() {
+    let app = app();
+    let channel = app.state.channel(2).unwrap();
+    let mut cache = cache();
+    for width in [30, 45] {
+        let buffer = render(width, 10, |frame| {
+            let widget = ChatWidget::ui(
+                channel.name.clone(),
+                Rows::new(
+                    &channel.messages,
+                    |_| Visibility::Shown,
+                    StatusMessages::Shown,
+                ),
+                &mut Runs::default(),
+                &mut cache,
+                frame.size(),
+            );
+            frame.render_widget(widget, frame.size());
+        });
+        assert_snapshot(&format!("chat_widget_wrapped_{width}"), &buffer);
+    }
+}
+
+#[test]
+fn users_widget() {
+    let app = app();
+    let channel = app.state.channel(2).unwrap();
+    let mut list = UserList::default();
+    let buffer = render(20, 10, |frame| {
+        let widget = UsersWidget::ui(&channel.users, &mut list);
+        frame.render_stateful_widget(widget, frame.size(), &mut list.state);
+    });
+    assert_snapshot("users_widget", &buffer);
+}
+
+#[test]
+fn users_widget_filtered_with_selection() {
+    let app = app();
+    let channel = app.state.channel(2).unwrap();
+    let mut list = UserList::default();
+    list.focused = true;
+    list.push_filter('R');
+    list.select_next(2);
+    assert_eq!(
+        list.selected(&channel.users).unwrap().nick.as_deref(),
+        Some("crab")
+    );
+
+    let buffer = render(20, 8, |frame| {
+        let widget = UsersWidget::ui(&channel.users, &mut list);
+        frame.render_stateful_widget(widget, frame.size(), &mut list.state);
+    });
+    assert_snapshot("users_widget_filtered", &buffer);
+}
+
+#[test]
+fn input_widget() {
+    let buffer = render(30, 3, |frame| {
+        frame.render_widget(InputWidget::ui("hello world"), frame.size());
+    });
+    assert_snapshot("input_widget", &buffer);
+}
+
+#[test]
+fn log_widget() {
+    let view = LogView {
+        title: "#rust.log".into(),
+        lines: (1..=10).map(|i| format!("line {i}")).collect(),
+        scroll: 2,
+    };
+    let buffer = render(30, 6, |frame| {
+        frame.render_widget(LogWidget::ui(&view), frame.size());
+    });
+    assert_snapshot("log_widget", &buffer);
+}
+
+#[test]
+fn whois_target_from_the_chat() {
+    let mut app = app();
+    // Newest message of #rust is an action of ferris
+    assert_eq!(app.whois_target().as_deref(), Some("ferris"));
+
+    app.input_buffer.set("crab: hello".into());
+    app.input_buffer.pos = 2;
+    assert_eq!(app.whois_target().as_deref(), Some("crab"));
+
+    // Words that aren't nicks in the channel are ignored
+    app.input_buffer.set("hello world".into());
+    assert_eq!(app.whois_target().as_deref(), Some("ferris"));
+}
+
+#[test]
+fn log_view_takes_the_keys() {
+    let mut app = app();
+    app.log_view = Some(LogView {
+        title: "#rust.log".into(),
+        lines: (1..=10).map(|i| format!("line {i}")).collect(),
+        scroll: 0,
+    });
+
+    app.key_event(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::NONE));
+    app.key_event(KeyEvent::new(KeyCode::Up, KeyModifiers::ALT));
+    assert_eq!(app.input_buffer.text, "");
+    assert_eq!(app.state.active(), 2);
+    assert_eq!(app.log_view.as_ref().unwrap().scroll, 1);
+
+    app.key_event(KeyEvent::new(KeyCode::Char('l'), KeyModifiers::ALT));
+    assert!(app.log_view.is_none());
+}
+
+#[test]
+fn caches_are_dropped_when_the_lounge_restarts() {
+    let transport = FakeTransport::default();
+    let state = TuiState::with_transport(Box::new(transport.clone()), &Config::default());
+    let mut app = TuiApp::with_state(state);
+    app.wrap_cache = cache();
+    let config: Config = toml::from_str("[[filters]]\ntext = 'everyone'\naction = 'hide'").unwrap();
+    app.filters = Filters::new(config.filters);
+
+    let mut init: Value = serde_json::from_str(INIT).unwrap();
+    transport.push(Event::Init(serde_json::from_value(init.clone()).unwrap()));
+    app.update();
+    let buffer = render(60, 12, |frame| ui(frame, &mut app));
+    assert!(buffer.contains("hi ferris") && !buffer.contains("hello everyone"));
+
+    // Ids restart with the server, so 11 is now another message
+    init["networks"][0]["channels"][1]["messages"][1]["text"] = json!("after the restart");
+    transport.push(Event::Init(serde_json::from_value(init).unwrap()));
+    app.update();
+    let buffer = render(60, 12, |frame| ui(frame, &mut app));
+    assert!(buffer.contains("after the restart"));
+}
+
+#[test]
+fn whois_popup() {
+    let whois = serde_json::from_value(whois()).unwrap();
+    let buffer = render(60, 14, |frame| {
+        let area = WhoisWidget::area(&whois, frame.size());
+        frame.render_widget(Clear, area);
+        frame.render_widget(WhoisWidget::ui(&whois), area);
+    });
+    assert_snapshot("whois_popup", &buffer);
+}
+
+#[test]
+fn chat_widget_whois_reply() {
+    let msg = json!({
+        "from": {}, "gecos": null, "hostmask": null, "id": 50, "self": false, "text": "",
+        "time": "2024-04-20T11:00:00.000Z", "type": "whois", "whois": whois(),
+    });
+    let messages = [serde_json::from_value(msg).unwrap()];
+    let buffer = render(50, 12, |frame| {
+        let widget = ChatWidget::ui(
+            "#rust".into(),
+            Rows::new(&messages, |_| Visibility::Shown, StatusMessages::Shown),
+            &mut Runs::default(),
+            &mut cache(),
+            frame.size(),
+        );
+        frame.render_widget(widget, frame.size());
+    });
+    assert_snapshot("chat_widget_whois", &buffer);
+}
+
+#[test]
+fn chat_widget_condensed_status_messages() {
+    let types = ["message", "join", "join", "quit", "message", "part", "nick"];
+    let messages: Vec<_> = (1..)
+        .zip(types)
+        .map(|(id, type_)| {
+            let msg = json!({
+                "from": {"mode": "", "nick": "crab"}, "gecos": null, "hostmask": null, "id": id,
+                "self": false, "text": "hi", "time": "2024-04-20T11:00:00.000Z", "type": type_,
+            });
+            serde_json::from_value(msg).unwrap()
+        })
+        .collect();
+
+    let mut runs = Runs::default();
+    runs.expanded.insert(6);
+    let buffer = render(50, 10, |frame| {
+        let widget = ChatWidget::ui(
+            "#rust".into(),
+            Rows::new(&messages, |_| Visibility::Shown, StatusMessages::Condensed),
+            &mut runs,
+            &mut cache(),
+            frame.size(),
+        );
+        frame.render_widget(widget, frame.size());
+    });
+    assert_snapshot("chat_widget_condensed", &buffer);
+}
+
+#[test]
+fn topic_bar() {
+    let topic = "\x02Rust\x02 programming language | \x0312https://www.rust-lang.org";
+    let buffer = render(30, 4, |frame| {
+        let [line, expanded] =
+            Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).areas(frame.size());
+        frame.render_widget(TopicWidget::ui(topic, false), line);
+        let height = TopicWidget::height(topic, true, expanded.width);
+        assert_eq!(height, 2);
+        frame.render_widget(TopicWidget::ui(topic, true), expanded);
+    });
+    assert_snapshot("topic_bar", &buffer);
+}
+
+#[test]
+fn mentions_widget() {
+    let app = app();
+    let mentions: Vec<_> = serde_json::from_value(json!([
+        {"chanId": 2, "msgId": 11, "type": "message", "time": "2024-04-20T10:00:00.000Z",
+         "text": "\x02me\x02: review please", "from": {"mode": "@", "nick": "crab"}},
+        {"chanId": 4, "msgId": 20, "type": "message", "time": "2024-04-19T23:59:00.000Z",
+         "text": "hey me", "from": {"mode": "", "nick": "ferris"}},
+        {"chanId": 6, "msgId": 30, "type": "message", "time": "2024-04-20T09:05:00.000Z",
+         "text": "apt question for me", "from": {"mode": "", "nick": "deb"}},
+    ]))
+    .unwrap();
+
+    let mut list = MentionList::default();
+    list.select_next();
+    let buffer = render(50, 10, |frame| {
+        let widget = MentionsWidget::ui(&mentions, &app.state, &mut list, TimeZone::utc());
+        frame.render_stateful_widget(widget, frame.size(), &mut list.state);
+    });
+    assert_snapshot("mentions_widget", &buffer);
+}
+
+#[test]
+fn sessions_widget() {
+    let sessions: Vec<_> = serde_json::from_value(json!([
+        {"current": true, "active": true, "lastUse": 1_713_616_000_000u64, "ip": "::1", "agent": "thelounge-cli", "token": "a"},
+        {"current": false, "active": false, "lastUse": 1_713_605_640_000u64, "ip": "10.0.0.2", "agent": "Firefox on Linux", "token": "b"},
+        {"current": false, "active": false, "lastUse": 1_713_000_000_000u64, "ip": "10.0.0.3", "agent": "Chrome on Android", "token": "c"},
+    ]))
+    .unwrap();
+    let now = UNIX_EPOCH + Duration::from_secs(1_713_616_440);
+
+    let mut list = SessionList::default();
+    list.select_next();
+    list.toggle_mark(&sessions);
+    list.select_next();
+    let buffer = render(70, 6, |frame| {
+        let widget = SessionsWidget::ui(&sessions, &mut list, now);
+        frame.render_stateful_widget(widget, frame.size(), &mut list.state);
+    });
+    assert_snapshot("sessions_widget", &buffer);
+}
@@ -0,0 +1,37 @@
+use ratatui::{
+    style::{Color, Style},
+    text::Line,
+    widgets::{Paragraph, Wrap},
+};
+
+use super::{chat::wrap, format::irc_spans};
+
+pub struct TopicWidget {}
+
+impl TopicWidget {
+    /// Topic on one line, or wrapped over as many as needed when expanded
+    pub fn ui(topic: &str, expanded: bool) -> Paragraph<'static> {
+        let paragraph = Paragraph::new(Line::from(irc_spans(topic)))
+            .style(Style::default().bg(Color::DarkGray));
+
+        if expanded {
+            paragraph.wrap(Wrap { trim: false })
+        } else {
+            paragraph
+        }
+    }
+
+    /// Rows the topic needs at the given width
+    pub fn height(topic: &str, expanded: bool, width: u16) -> u16 {
+        if !expanded {
+            return 1;
+        }
+        // Same word wrapping as the chat, on the text without formatting codes
+        let text: String = irc_spans(topic)
+            .iter()
+            .map(|span| span.content.as_ref())
+            .collect();
+        let lines = wrap("", &text, usize::from(width)).len();
+        u16::try_from(lines).unwrap_or(u16::MAX).max(1)
+    }
+}
@@ -1,27 +1,159 @@
 use crate::types::User;
 use ratatui::{
+    style::{Color, Modifier, Style, Stylize},
     text::Span,
-    widgets::{Block, Borders, List, ListItem},
+    widgets::{Block, Borders, List, ListItem, ListState},
 };
 
+/// Mode groups in the order they're listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Group {
+    Operators,
+    Voiced,
+    Users,
+}
+
+impl Group {
+    const ALL: [Group; 3] = [Group::Operators, Group::Voiced, Group::Users];
+
+    fn of(user: &User) -> Self {
+        match user.mode.as_deref().and_then(|mode| mode.chars().next()) {
+            Some('~' | '&' | '@' | '%') => Group::Operators,
+            Some('+') => Group::Voiced,
+            _ => Group::Users,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Group::Operators => "Operators",
+            Group::Voiced => "Voiced",
+            Group::Users => "Users",
+        }
+    }
+}
+
+/// Filter and selection of the user list
+#[derive(Default)]
+pub struct UserList {
+    /// Keys go to the user list instead of the input
+    pub focused: bool,
+    /// Only nicks containing this are listed, case insensitive
+    filter: String,
+    /// Index into the filtered users
+    selected: usize,
+    /// Scroll position of the rendered list
+    pub state: ListState,
+}
+
+impl UserList {
+    pub fn push_filter(&mut self, c: char) {
+        self.filter.push(c);
+        self.selected = 0;
+    }
+
+    pub fn pop_filter(&mut self) {
+        self.filter.pop();
+        self.selected = 0;
+    }
+
+    /// Unfocus the list and forget the filter
+    pub fn close(&mut self) {
+        self.focused = false;
+        self.filter.clear();
+        self.selected = 0;
+    }
+
+    pub fn select_prev(&mut self, count: usize) {
+        self.selected = self.selected.saturating_sub(count);
+    }
+
+    pub fn select_next(&mut self, count: usize) {
+        self.selected += count;
+    }
+
+    /// Highlighted user of the list
+    pub fn selected<'a>(&self, users: &'a [User]) -> Option<&'a User> {
+        let users = self.filtered(users);
+        let selected = self.selected.min(users.len().checked_sub(1)?);
+        users.get(selected).map(|(_, user)| *user)
+    }
+
+    /// Users matching the filter, grouped by mode
+    fn filtered<'a>(&self, users: &'a [User]) -> Vec<(Group, &'a User)> {
+        let filter = self.filter.to_lowercase();
+        Group::ALL
+            .into_iter()
+            .flat_map(|group| {
+                users
+                    .iter()
+                    .filter(move |user| Group::of(user) == group)
+                    .map(move |user| (group, user))
+            })
+            .filter(|(_, user)| nick(user).to_lowercase().contains(&filter))
+            .collect()
+    }
+}
+
+fn nick(user: &User) -> &str {
+    user.nick.as_deref().unwrap_or("*")
+}
+
 pub struct UsersWidget {}
 
 impl UsersWidget {
-    pub fn ui(users: &[User]) -> List<'_> {
-        let user_list: Vec<ListItem> = users
-            .iter()
-            .filter(|usr| usr.nick.is_some())
-            .map(|usr| {
-                let nick = usr.nick.as_ref().unwrap();
-                let nick = if let Some(mode) = &usr.mode {
-                    format!("{mode}{nick}")
+    /// Selection of `list` is updated to point to the row of the selected
+    /// user, render the widget with `list.state`
+    pub fn ui<'a>(users: &'a [User], list: &mut UserList) -> List<'a> {
+        let filtered = list.filtered(users);
+        if !filtered.is_empty() {
+            list.selected = list.selected.min(filtered.len() - 1);
+        }
+
+        let mut items = Vec::new();
+        let mut selected_row = None;
+        for group in Group::ALL {
+            let members: Vec<&User> = filtered
+                .iter()
+                .filter(|(g, _)| *g == group)
+                .map(|(_, user)| *user)
+                .collect();
+            if members.is_empty() {
+                continue;
+            }
+
+            let header = Span::raw(format!("{} ({})", group.name(), members.len())).bold();
+            items.push(ListItem::new(header));
+            for user in members {
+                if list.focused && std::ptr::eq(user, filtered[list.selected].1) {
+                    selected_row = Some(items.len());
+                }
+
+                let mode = user.mode.as_deref().unwrap_or_default();
+                let style = if user.is_away() {
+                    Style::default().add_modifier(Modifier::DIM)
                 } else {
-                    nick.into()
+                    Style::default()
                 };
-                ListItem::new(Span::raw(nick))
-            })
-            .collect();
+                items.push(ListItem::new(Span::raw(format!(" {mode}{}", nick(user)))).style(style));
+            }
+        }
+        list.state.select(selected_row);
+
+        let title = if list.filter.is_empty() {
+            format!("Users ({})", users.len())
+        } else {
+            format!("Users ({}/{}) {}", filtered.len(), users.len(), list.filter)
+        };
+        let block = Block::default().borders(Borders::ALL).title(title);
+        let block = if list.focused {
+            block.border_style(Style::default().fg(Color::Yellow))
+        } else {
+            block
+        };
 
-        List::new(user_list).block(Block::default().borders(Borders::ALL).title("users"))
+        List::new(items)
+            .block(block)
+            .highlight_style(Style::default().reversed())
     }
 }
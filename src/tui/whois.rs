@@ -0,0 +1,110 @@
+use ratatui::{
+    layout::{Constraint, Layout, Rect},
+    style::Stylize,
+    text::{Line, Span},
+    widgets::{Block, Borders, Paragraph, Wrap},
+};
+
+use unicode_width::UnicodeWidthStr;
+
+use crate::types::Whois;
+
+/// Labeled fields of the reply that have a value
+pub fn fields(whois: &Whois) -> Vec<(&'static str, String)> {
+    let mut fields = Vec::new();
+
+    if let (Some(ident), Some(host)) = (&whois.ident, &whois.hostname) {
+        fields.push(("Host", format!("{ident}@{host}")));
+    }
+    if let Some(real_name) = &whois.real_name {
+        fields.push(("Real name", real_name.clone()));
+    }
+    match (&whois.server, &whois.server_info) {
+        (Some(server), Some(info)) => fields.push(("Server", format!("{server} ({info})"))),
+        (Some(server), None) => fields.push(("Server", server.clone())),
+        _ => {}
+    }
+    if let Some(channels) = &whois.channels {
+        fields.push(("Channels", channels.clone()));
+    }
+    if let Some(idle) = whois.idle {
+        fields.push(("Idle", format_duration(idle)));
+    }
+    if let Some(account) = &whois.account {
+        fields.push(("Account", account.clone()));
+    }
+    if let Some(secure) = whois.secure {
+        let secure = if secure { "yes" } else { "no" };
+        fields.push(("Secure", secure.into()));
+    }
+    if let Some(away) = &whois.away {
+        fields.push(("Away", away.clone()));
+    }
+
+    fields
+}
+
+/// Duration in the largest units, e.g. `1h 2m 3s`
+fn format_duration(seconds: u64) -> String {
+    let units = [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")];
+    let mut left = seconds;
+    let parts: Vec<String> = units
+        .iter()
+        .filter_map(|&(size, unit)| {
+            let count = left / size;
+            left %= size;
+            (count > 0).then(|| format!("{count}{unit}"))
+        })
+        .collect();
+
+    if parts.is_empty() {
+        "0s".into()
+    } else {
+        parts.join(" ")
+    }
+}
+
+pub struct WhoisWidget {}
+
+impl WhoisWidget {
+    pub fn ui(whois: &Whois) -> Paragraph<'_> {
+        let lines: Vec<Line> = fields(whois)
+            .into_iter()
+            .map(|(label, value)| {
+                Line::from(vec![Span::raw(format!("{label}: ")).bold(), value.into()])
+            })
+            .collect();
+
+        Paragraph::new(lines).wrap(Wrap { trim: false }).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Whois {}", whois.nick)),
+        )
+    }
+
+    /// Centered area for the popup, high enough for the wrapped fields
+    pub fn area(whois: &Whois, area: Rect) -> Rect {
+        let [_, column, _] = Layout::horizontal([
+            Constraint::Fill(1),
+            Constraint::Percentage(60),
+            Constraint::Fill(1),
+        ])
+        .areas(area);
+
+        let width = usize::from(column.width.saturating_sub(2)).max(1);
+        let lines: usize = fields(whois)
+            .iter()
+            .map(|(label, value)| (label.width() + 2 + value.width()).div_ceil(width))
+            .sum();
+        // Fields and the borders
+        let height = u16::try_from(lines + 2).unwrap_or(u16::MAX);
+
+        let [_, area, _] = Layout::vertical([
+            Constraint::Fill(1),
+            Constraint::Length(height),
+            Constraint::Fill(1),
+        ])
+        .areas(column);
+        area
+    }
+}
@@ -1,4 +1,10 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Lounge uses -1 when there's no channel. Map it into 0 which is never used
+/// as a channel id.
+fn channel_id_or_zero<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    Ok(u32::try_from(i64::deserialize(deserializer)?).unwrap_or(0))
+}
 
 /// Name is similar to User execpt with more detailed information
 #[derive(Debug, Serialize, Deserialize)]
@@ -7,6 +13,9 @@ pub struct Name {
     pub modes: Vec<String>,
     #[serde(rename = "lastMessage")]
     pub last_message: u64,
+    /// Away message, empty if the user isn't away
+    #[serde(default)]
+    pub away: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -21,6 +30,15 @@ pub struct User {
     // TODO: these should always exists. Fix parsing of lobby messsage
     pub mode: Option<String>,
     pub nick: Option<String>,
+    /// Away message if the user is away
+    #[serde(default)]
+    pub away: Option<String>,
+}
+
+impl User {
+    pub fn is_away(&self) -> bool {
+        self.away.as_deref().is_some_and(|away| !away.is_empty())
+    }
 }
 
 impl From<&Name> for User {
@@ -30,6 +48,7 @@ impl From<&Name> for User {
         Self {
             mode,
             nick: Some(value.nick.clone()),
+            away: Some(value.away.clone()).filter(|away| !away.is_empty()),
         }
     }
 }
@@ -51,6 +70,30 @@ pub struct ChannelMessage {
     // TODO: enums for type
     #[serde(rename = "type")]
     pub type_: String,
+    /// Reply to `/whois` in messages of type `whois`
+    pub whois: Option<Box<Whois>>,
+}
+
+/// Structured reply of `/whois`. Only the nick is always present.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Whois {
+    pub nick: String,
+    pub ident: Option<String>,
+    pub hostname: Option<String>,
+    pub real_name: Option<String>,
+    pub server: Option<String>,
+    /// Description of the server
+    pub server_info: Option<String>,
+    /// Space separated channels with mode prefixes
+    pub channels: Option<String>,
+    /// Seconds since the user's last message
+    pub idle: Option<u64>,
+    /// Services account the user is logged into
+    pub account: Option<String>,
+    /// Connected with TLS
+    pub secure: Option<bool>,
+    /// Away message
+    pub away: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -72,8 +115,17 @@ pub struct NetworkChannel {
     pub unread: i32,
     pub messages: Vec<ChannelMessage>,
     pub users: Vec<User>,
+    /// Names and the messages newer than the ones in `init` have been
+    /// fetched. Older messages are fetched on demand while scrolling, as long
+    /// as there are less messages than `total_messages`.
     #[serde(default)]
     pub loaded: bool,
+    /// Waiting for the answer to a `more` request
+    #[serde(skip)]
+    pub fetching: bool,
+    /// Modes from the last `mode_channel` reply, e.g. `+nt`
+    #[serde(skip)]
+    pub modes: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -96,34 +148,30 @@ impl Network {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Init {
+    /// Id of the active channel, 0 if there's none
+    #[serde(deserialize_with = "channel_id_or_zero")]
     pub active: u32,
     pub networks: Vec<Network>,
     pub token: String,
 }
 
-impl Init {
-    pub fn active_channel(&self) -> Option<&NetworkChannel> {
-        for network in &self.networks {
-            let channel = network.channel(self.active);
-            if channel.is_some() {
-                return channel;
-            }
-        }
-
-        None
-    }
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Msg {
     pub chan: u32,
     pub msg: ChannelMessage,
+    /// New unread count of the channel, if the message increased it
+    pub unread: Option<i32>,
+    /// New highlight count of the channel, if the message was a highlight
+    pub highlight: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct More {
     pub chan: u32,
     pub messages: Vec<ChannelMessage>,
+    /// Messages the server has for the channel
+    #[serde(rename = "totalMessages")]
+    pub total_messages: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -133,4 +181,95 @@ pub struct Join {
     /// Index in the network channels list
     pub index: usize,
     pub chan: NetworkChannel,
+    /// False when the channel shouldn't be focused, e.g. a query someone
+    /// else started
+    #[serde(rename = "shouldOpen")]
+    pub should_open: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Part {
+    /// Id of the channel that was left or closed
+    pub chan: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Quit {
+    /// Uuid of the network that was removed
+    pub network: String,
+}
+
+/// New ordering of networks or channels, sent to every client after a `sort`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SyncSort {
+    Networks {
+        order: Vec<String>,
+    },
+    Channels {
+        /// Target network uuid
+        target: String,
+        order: Vec<u32>,
+    },
+}
+
+/// Settings of the Lounge instance the clients need
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Configuration {
+    #[serde(rename = "fileUpload", default)]
+    pub file_upload: bool,
+    /// Largest upload in bytes, negative if there's no limit
+    #[serde(rename = "fileUploadMaxFileSize", default)]
+    pub file_upload_max_file_size: i64,
+}
+
+/// Synced setting changed by another client
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SettingNew {
+    pub name: String,
+    pub value: serde_json::Value,
+}
+
+/// Logged in client of the user, from `sessions:list`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    /// Session of this client
+    pub current: bool,
+    /// Connected right now
+    pub active: bool,
+    /// Unix time in milliseconds
+    #[serde(rename = "lastUse")]
+    pub last_use: u64,
+    pub ip: String,
+    pub agent: String,
+    pub token: String,
+}
+
+/// Highlight of the user, from `mentions:list`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Mention {
+    /// Channel the message was sent to
+    #[serde(rename = "chanId")]
+    pub chan_id: u32,
+    #[serde(rename = "msgId")]
+    pub msg_id: u32,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub time: String,
+    pub text: String,
+    pub from: User,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Topic {
+    pub chan: u32,
+    pub topic: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MuteChanged {
+    /// Id of the channel whose mute status changed
+    pub target: u32,
+    /// Is the channel muted now
+    pub status: bool,
 }
@@ -0,0 +1,208 @@
+//! File uploads to the upload endpoint of the Lounge
+
+use reqwest::blocking::{Body, Client};
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+        Arc,
+    },
+    thread,
+};
+
+/// Answer of the upload endpoint
+#[derive(Debug, Deserialize)]
+struct Response {
+    url: Option<String>,
+    error: Option<String>,
+}
+
+/// Upload running in a background thread
+pub struct Upload {
+    /// Name of the uploaded file
+    pub name: String,
+    pub size: u64,
+    /// Bytes of the file sent so far
+    sent: Arc<AtomicU64>,
+    result: Receiver<Result<String, String>>,
+}
+
+impl Upload {
+    /// Start uploading the file with a token from `upload:auth`
+    pub fn start(base_url: &str, token: &str, path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| format!("{}: {err}", path.display()))?;
+        let size = file
+            .metadata()
+            .map_err(|err| format!("{}: {err}", path.display()))?
+            .len();
+        let name = path
+            .file_name()
+            .map_or("upload".into(), |name| name.to_string_lossy().into_owned());
+
+        let base_url = base_url.trim_end_matches('/').to_string();
+        let url = format!("{base_url}/uploads/new/{token}");
+        let sent = Arc::new(AtomicU64::new(0));
+        let (tx, result) = mpsc::channel();
+        {
+            let name = name.clone();
+            let sent = sent.clone();
+            thread::spawn(move || {
+                let result = post(&url, &name, file, size, sent)
+                    .map(|uploaded| resolve(&base_url, &uploaded));
+                let _ = tx.send(result);
+            });
+        }
+
+        Ok(Self {
+            name,
+            size,
+            sent,
+            result,
+        })
+    }
+
+    /// Sent part of the file in percent
+    pub fn percent(&self) -> u64 {
+        let sent = self.sent.load(Ordering::Relaxed);
+        (sent * 100).checked_div(self.size).unwrap_or(100)
+    }
+
+    /// URL of the uploaded file or the error, once the upload is done
+    pub fn poll(&self) -> Option<Result<String, String>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("Upload failed".into())),
+        }
+    }
+}
+
+/// Counts the bytes read through it
+struct Progress<R> {
+    inner: R,
+    sent: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for Progress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.sent.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// Post the file as `multipart/form-data`, like the web client does
+fn post(
+    url: &str,
+    name: &str,
+    file: File,
+    size: u64,
+    sent: Arc<AtomicU64>,
+) -> Result<String, String> {
+    let boundary = "----thelounge-cli-upload";
+    let name = name.replace(['"', '\r', '\n'], "_");
+    let head = format!(
+        "--{boundary}\r\n\
+         Content-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n"
+    );
+    let tail = format!("\r\n--{boundary}--\r\n");
+    let length = head.len() as u64 + size + tail.len() as u64;
+
+    let body = Cursor::new(head)
+        .chain(Progress { inner: file, sent })
+        .chain(Cursor::new(tail));
+
+    let response = Client::builder()
+        // Large files take a while
+        .timeout(None)
+        .build()
+        .map_err(|err| err.to_string())?
+        .post(url)
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={boundary}"),
+        )
+        .body(Body::sized(body, length))
+        .send()
+        .map_err(|err| err.to_string())?;
+
+    let status = response.status();
+    let response: Response = response
+        .text()
+        .ok()
+        .and_then(|body| serde_json::from_str(&body).ok())
+        .ok_or_else(|| format!("Upload failed with {status}"))?;
+    match (response.url, response.error) {
+        (_, Some(error)) => Err(error),
+        (Some(url), None) => Ok(url),
+        (None, None) => Err(format!("Upload failed with {status}")),
+    }
+}
+
+/// Path with a leading `~` replaced by the home directory
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), dirs::home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+            home.join(rest.trim_start_matches('/'))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Size in the largest binary unit, e.g. `1.5 MiB`
+pub fn format_size(bytes: u64) -> String {
+    let units = ["KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = "bytes";
+    for next in units {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+
+    if unit == "bytes" {
+        format!("{bytes} bytes")
+    } else {
+        format!("{size:.1} {unit}")
+    }
+}
+
+/// The Lounge answers with URLs relative to the instance
+fn resolve(base_url: &str, url: &str) -> String {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        url.into()
+    } else {
+        format!("{base_url}/{}", url.trim_start_matches('/'))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_size, resolve};
+
+    #[test]
+    fn relative_urls_are_resolved() {
+        assert_eq!(
+            resolve("https://irc.example.org/lounge", "uploads/ab12/cat.png"),
+            "https://irc.example.org/lounge/uploads/ab12/cat.png"
+        );
+        assert_eq!(
+            resolve("https://irc.example.org", "https://cdn.example.org/cat.png"),
+            "https://cdn.example.org/cat.png"
+        );
+    }
+
+    #[test]
+    fn sizes_in_binary_units() {
+        assert_eq!(format_size(1000), "1000 bytes");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(10 * 1024 * 1024), "10.0 MiB");
+    }
+}